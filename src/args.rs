@@ -0,0 +1,132 @@
+//! Command-line arguments. Without any, BASIClings runs interactively.
+
+use std::path::PathBuf;
+
+pub const USAGE: &str = "Usage:
+  basiclings [--lessons-dir <dir>]
+  basiclings test [--lessons-dir <dir>] --lesson <id> [--save] [--format <text|json>] <submission file>
+  basiclings check-lessons [--lessons-dir <dir>] [--verify]
+
+Options:
+  --lessons-dir <dir>  Also load Markdown lessons from this directory. They replace built-in
+                       lessons with the same id. Overrides lessons_dir in basiclings_config.json.
+
+Options for test:
+  --lesson <id>      The lesson to test the submission against.
+  --save             Record the attempt, and the lesson's completion, in the save file.
+  --format <format>  Print results as text (the default) or as a single JSON object.
+
+Options for check-lessons:
+  --verify           Also test that each lesson's starting program behaves as its tests
+                     expect, and that its reference solutions pass. This can take a while.";
+
+pub struct Arguments {
+    pub command: Command,
+    pub lessons_dir: Option<PathBuf>,
+}
+
+pub enum Command {
+    Interactive,
+    Test(TestOptions),
+    /// `basiclings check-lessons`, which reports mistakes in the lessons. With `verify`, the
+    /// lessons' programs are tested as well.
+    CheckLessons {
+        verify: bool,
+    },
+}
+
+/// `basiclings test`, which grades a submission without any prompts.
+pub struct TestOptions {
+    pub lesson: u16,
+    pub submission: PathBuf,
+    pub save: bool,
+    pub format: OutputFormat,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Arguments, String> {
+    // options shared by every command may appear anywhere
+    let mut lessons_dir = None;
+    let mut remaining = Vec::new();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--lessons-dir" => {
+                lessons_dir = Some(PathBuf::from(
+                    args.next().ok_or("--lessons-dir needs a directory.")?,
+                ));
+            }
+            _ => remaining.push(arg),
+        }
+    }
+
+    let mut args = remaining.into_iter();
+
+    let command = match args.next() {
+        None => Command::Interactive,
+        Some(command) => match command.as_str() {
+            "test" => Command::Test(parse_test(args)?),
+            "check-lessons" => {
+                let mut verify = false;
+
+                for arg in args {
+                    match arg.as_str() {
+                        "--verify" => verify = true,
+                        _ => return Err(format!("Unexpected argument \"{}\".", arg)),
+                    }
+                }
+
+                Command::CheckLessons { verify }
+            }
+            _ => return Err(format!("Unknown command \"{}\".", command)),
+        },
+    };
+
+    Ok(Arguments {
+        command,
+        lessons_dir,
+    })
+}
+
+fn parse_test(mut args: impl Iterator<Item = String>) -> Result<TestOptions, String> {
+    let mut lesson = None;
+    let mut submission = None;
+    let mut save = false;
+    let mut format = OutputFormat::default();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--lesson" => {
+                let id = args.next().ok_or("--lesson needs a lesson id.")?;
+                lesson = Some(
+                    id.parse::<u16>()
+                        .map_err(|_| format!("\"{}\" is not a lesson id.", id))?,
+                );
+            }
+            "--save" => save = true,
+            "--format" => {
+                format = match args.next().as_deref() {
+                    Some("text") => OutputFormat::Text,
+                    Some("json") => OutputFormat::Json,
+                    _ => return Err("--format needs to be text or json.".to_owned()),
+                };
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unknown option \"{}\".", arg)),
+            _ if submission.is_none() => submission = Some(PathBuf::from(arg)),
+            _ => return Err(format!("Unexpected argument \"{}\".", arg)),
+        }
+    }
+
+    Ok(TestOptions {
+        lesson: lesson.ok_or("Missing --lesson.")?,
+        submission: submission.ok_or("Missing the submission file.")?,
+        save,
+        format,
+    })
+}
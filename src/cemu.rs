@@ -4,17 +4,29 @@ use std::{
     fmt::Display,
     fs, io,
     path::{Path, PathBuf},
-    process::{Command, ExitStatus, Stdio},
+    process::{Child, Command, ExitStatus, Stdio},
+    thread,
+    time::{Duration, Instant},
 };
 
 use deku::prelude::*;
 use fancy_regex::Regex;
-use serde::Serialize;
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+use tifloats::Float;
 use titokens::Tokens;
 
 use crate::{
-    lesson::{Test, Variable, VariableData},
-    tools::{float_to_tifloat, tokenize, tokenizer},
+    interpreter::{variable_names, ErrorKind, InterpreterBackend},
+    keypad::Key,
+    lesson::{
+        Comparison, Complex, DisplayLine, ExpectedError, Graph, Lesson, Output, Speed, Test,
+        Variable, VariableData, CUSTOM_LIST_PREFIX,
+    },
+    screen::{crc32, HomeScreen, COLUMNS, ROWS, TEXT_SHADOW_ADDRESS},
+    tools::{
+        complex_to_raw_bytes, float_literal, float_to_tifloat, tifloat_to_float, tokenize,
+        tokenizer,
+    },
 };
 
 #[derive(Debug)]
@@ -24,6 +36,21 @@ pub enum TestError {
     TIFileParsing(deku::DekuError),
     CEmuCrashed(ExitStatus),
     Regex(Box<fancy_regex::Error>),
+    UnreadableVariable(u8),
+    /// The built-in interpreter can't run the program, named by what it doesn't support.
+    Unsupported(String),
+    /// The lesson's starting program couldn't provide the expected value of an output given only
+    /// by name, for the given reason.
+    UnknownOutput(String),
+    /// A test's expected home screen can't be shown, for the given reason.
+    Display(String),
+    /// A test's reference program couldn't provide the expected graph screen, for the given
+    /// reason.
+    UnknownGraph(String),
+    /// The lesson's starting program didn't finish its speed test in this many seconds.
+    UnknownSpeed(u32),
+    /// The submission can't be tokenized, at the given line.
+    Untokenizable(String),
 }
 
 impl Display for TestError {
@@ -34,15 +61,238 @@ impl Display for TestError {
             TestError::TIFileParsing(deku_error) => writeln!(f, "Error parsing 8x file during tests:\n{}", deku_error),
             TestError::CEmuCrashed(exit_status) => writeln!(f, "CEmu crashed during tests: {}", exit_status),
             TestError::Regex(error) => writeln!(f, "Error parsing test regex: {}", error),
+            TestError::UnreadableVariable(file_type) => writeln!(f, "Could not read a variable of type {:#04x} exported during tests.", file_type),
+            TestError::Unsupported(what) => writeln!(f, "Without a rom file, programs are tested with a built-in interpreter, which does not support {}.\nPlace a TI84+CE rom file in the current directory to test this program with CEmu.", what),
+            TestError::UnknownOutput(reason) => writeln!(f, "Could not find the expected outputs by running the lesson's starting program: {}.", reason),
+            TestError::Display(reason) => writeln!(f, "Error in a test's expected home screen: {}.", reason),
+            TestError::UnknownGraph(reason) => writeln!(f, "Could not find the expected graph screen by running the test's reference program: {}.", reason),
+            TestError::UnknownSpeed(seconds) => writeln!(f, "The lesson's starting program did not finish its speed test in {} seconds.", seconds),
+            TestError::Untokenizable(line) => writeln!(f, "Could not tokenize this line of the program: {}", line),
         }
     }
 }
 
-#[derive(Debug)]
+/// The submission, as transferred to the calculator.
+const PROGRAM_NAME: &str = "TESTPROG";
+/// Sets up each test case and runs the submission, see [driver_program].
+const DRIVER_NAME: &str = "TESTDRV";
+
+/// Every type an output can be exported as.
+const EXPORT_EXTENSIONS: [&str; 6] = ["8xn", "8xc", "8xl", "8xm", "8xs", "8xy"];
+
+/// Time for the calculator to boot and start the driver.
+const LAUNCH_DELAY_MS: u32 = 1500;
+/// Time for each case to run before its outputs are exported. A case which takes longer is run
+/// again on its own, and given the whole timeout.
+pub(crate) const CASE_DELAY_MS: u32 = 1500;
+/// Time for CEmu to start, load the ROM and transfer files, on top of a session's delays.
+const SESSION_OVERHEAD: Duration = Duration::from_secs(20);
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The graph screen is saved to this picture (`Pic0`) to compare it against a reference
+/// program's.
+const GRAPH_PICTURE: u8 = 0;
+/// The LCD's memory, 240 rows of 320 pixels which are 2 bytes each.
+const LCD_ADDRESS: u32 = 0xD40000;
+const LCD_ROWS: usize = 240;
+const LCD_ROW_BYTES: usize = 320 * 2;
+/// The busy indicator on the status bar keeps changing, so only the rows below it are hashed.
+const STATUS_BAR_ROWS: usize = 30;
+/// Where a speed test's driver keeps the time the runs started, followed by the whole seconds they
+/// took once they're done, then how many of its loops fit in the rest of that second, and in the
+/// whole second after it, see [timing_program].
+const TIMER_LIST: &str = "|LTIMER";
+/// The longest a speed test's driver waits for the clock to tick over before timing the runs.
+const TIMER_SYNC_MS: u32 = 1000;
+/// The longest a speed test's driver takes to measure how far into a second the runs finished.
+const TIMER_FRACTION_MS: u32 = 2000;
+/// Set by the driver before each case and deleted once the program returns to it, so that a case
+/// which is still running when its outputs are exported can be told apart from one which finished.
+const RUNNING_LIST: &str = "|LRUN";
+/// Where the calculator keeps the number of the last error, which is 0 until there is one.
+const ERR_NO_ADDRESS: u32 = 0xD008DF;
+/// Set in `errNo` alongside the error's number when the error screen offers `Goto`.
+const ERR_NO_GOTO_FLAG: u8 = 0x80;
+
+#[derive(Debug, Clone)]
 pub enum ProgramTestResult {
     Pass,
     // a little more information about *what* failed
     Fail(String),
+    /// The program was stopped after running for this long.
+    Timeout(Duration),
+}
+
+impl ProgramTestResult {
+    pub fn passed(&self) -> bool {
+        matches!(self, ProgramTestResult::Pass)
+    }
+}
+
+impl Display for ProgramTestResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProgramTestResult::Pass => f.write_str("Tests passed."),
+            ProgramTestResult::Fail(reason) => f.write_str(reason),
+            ProgramTestResult::Timeout(timeout) => write!(
+                f,
+                "Program did not finish in {} seconds.",
+                timeout.as_secs_f64()
+            ),
+        }
+    }
+}
+
+/// eg. `{"status": "fail", "reason": "..."}`
+impl Serialize for ProgramTestResult {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("ProgramTestResult", 2)?;
+        match self {
+            ProgramTestResult::Pass => state.serialize_field("status", "pass")?,
+            ProgramTestResult::Fail(reason) => {
+                state.serialize_field("status", "fail")?;
+                state.serialize_field("reason", reason)?;
+            }
+            ProgramTestResult::Timeout(timeout) => {
+                state.serialize_field("status", "timeout")?;
+                state.serialize_field("seconds", &timeout.as_secs_f64())?;
+            }
+        }
+        state.end()
+    }
+}
+
+/// How long a program took for its lesson's speed test.
+#[derive(Serialize)]
+pub struct SpeedReport {
+    pub result: ProgramTestResult,
+    pub runs: u32,
+    /// `None` if the runs took longer than the lesson allows.
+    pub seconds: Option<f64>,
+    /// Only timed if the program can be no slower.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub starting_program_seconds: Option<f64>,
+}
+
+/// eg. `20 runs took 3 seconds (the starting program's took 4).`
+impl Display for SpeedReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Some(seconds) = self.seconds else {
+            return write!(f, "{}", self.result);
+        };
+
+        write!(f, "{} runs took {}", self.runs, seconds_text(seconds))?;
+        if let Some(starting_seconds) = self.starting_program_seconds {
+            write!(
+                f,
+                " (the starting program's took {})",
+                round_seconds(starting_seconds)
+            )?;
+        }
+        f.write_str(".")
+    }
+}
+
+fn seconds_text(seconds: f64) -> String {
+    match round_seconds(seconds) {
+        1.0 => "1 second".to_owned(),
+        seconds => format!("{} seconds", seconds),
+    }
+}
+
+/// Timings are only so precise, so they're shown to hundredths of a second.
+fn round_seconds(seconds: f64) -> f64 {
+    (seconds * 100.0).round() / 100.0
+}
+
+/// How one of a lesson's tests went, nested like the lesson's [Test]s.
+#[derive(Serialize)]
+pub struct TestReport {
+    /// `None` if the test was skipped because earlier tests had already decided the outcome.
+    pub result: Option<ProgramTestResult>,
+    /// Cases run in their group's shared session are only timed as part of the group.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seconds: Option<f64>,
+    #[serde(flatten)]
+    pub test: TestKind,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TestKind {
+    #[serde(rename = "cemu")]
+    CEmu {
+        /// eg. `A=1`
+        inputs: Vec<String>,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        seeds: Vec<u64>,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        keys: Vec<String>,
+    },
+    FulltextMatch {
+        regex: String,
+    },
+    TokenCount {
+        token: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        at_least: Option<usize>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        at_most: Option<usize>,
+    },
+    Random {
+        /// Unknown until the test has run, unless the lesson sets it.
+        seed: Option<u64>,
+        cases: usize,
+    },
+    Group {
+        tests: Vec<TestReport>,
+    },
+}
+
+impl TestKind {
+    fn of(test: &Test) -> Self {
+        match test {
+            Test::CEmu {
+                input, seeds, keys, ..
+            } => TestKind::CEmu {
+                inputs: input
+                    .iter()
+                    .map(|input| format!("{}={}", input.name, input.value))
+                    .collect(),
+                seeds: seeds.clone(),
+                keys: keys.iter().map(|key| key.to_string()).collect(),
+            },
+            Test::FulltextMatch { regex } => TestKind::FulltextMatch {
+                regex: regex.clone(),
+            },
+            Test::TokenCount {
+                token,
+                at_least,
+                at_most,
+            } => TestKind::TokenCount {
+                token: token.clone(),
+                at_least: *at_least,
+                at_most: *at_most,
+            },
+            Test::Random { cases, seed, .. } => TestKind::Random {
+                seed: *seed,
+                cases: *cases,
+            },
+            Test::Group(group) => TestKind::Group {
+                tests: group.iter().map(TestReport::skipped).collect(),
+            },
+        }
+    }
+}
+
+impl TestReport {
+    fn skipped(test: &Test) -> Self {
+        TestReport {
+            result: None,
+            seconds: None,
+            test: TestKind::of(test),
+        }
+    }
 }
 
 #[derive(Clone, Serialize)]
@@ -91,6 +341,53 @@ impl TIEntry {
         let bytes = self.to_bytes().unwrap();
         bytes.iter().fold(0, |a, &x| a.wrapping_add(x as u16))
     }
+
+    /// The size header and the real (or real and imaginary) parts of a numeric variable.
+    fn numeric_parts(&self) -> Option<(&[u8], Vec<Float>)> {
+        let header_length = match self.file_type & 0x1F {
+            0x00 | 0x0C => 0,
+            0x01 | 0x02 | 0x0D => 2,
+            _ => return None,
+        };
+
+        let (header, floats) = self.data.split_at_checked(header_length)?;
+        if floats.len() % 9 != 0 {
+            return None;
+        }
+
+        let floats = floats
+            .chunks_exact(9)
+            .map(|chunk| {
+                let mut bytes: [u8; 9] = chunk.try_into().unwrap();
+                // only the sign matters when comparing
+                bytes[0] &= 0x80;
+
+                Float::from_raw_bytes(bytes).ok()
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        Some((header, floats))
+    }
+
+    /// Whether this variable has the value of `expected`, given how numbers should be compared.
+    fn matches(&self, expected: &TIEntry, comparison: Comparison) -> bool {
+        if let Comparison::Exact = comparison {
+            return self.data == expected.data;
+        }
+
+        match (expected.numeric_parts(), self.numeric_parts()) {
+            (Some((expected_header, expected_floats)), Some((actual_header, actual_floats))) => {
+                expected.file_type & 0x1F == self.file_type & 0x1F
+                    && expected_header == actual_header
+                    && expected_floats.len() == actual_floats.len()
+                    && expected_floats
+                        .iter()
+                        .zip(actual_floats.iter())
+                        .all(|(expected, actual)| comparison.floats_match(expected, actual))
+            }
+            _ => self.data == expected.data,
+        }
+    }
 }
 
 impl PartialEq for TIEntry {
@@ -103,7 +400,15 @@ impl From<Variable> for TIEntry {
     fn from(value: Variable) -> Self {
         let mut name = [0u8; 8];
 
-        let name_tokens: Vec<u8> = <Tokens as Into<Vec<u8>>>::into(tokenize(&value.name))
+        // custom lists are named by the list type byte rather than the ʟ token
+        let name_bytes: Vec<u8> = match value.custom_list_name() {
+            Some(list_name) => std::iter::once(0x5D)
+                .chain(<Tokens as Into<Vec<u8>>>::into(tokenize(list_name)))
+                .collect(),
+            None => tokenize(&value.name).into(),
+        };
+
+        let name_tokens: Vec<u8> = name_bytes
             .into_iter()
             .chain(std::iter::repeat(0u8))
             .take(8)
@@ -119,6 +424,29 @@ impl From<Variable> for TIEntry {
                 (0x04, len)
             }
 
+            VariableData::Equation { equation } => {
+                let mut token_bytes: Vec<u8> = tokenize(&equation).into();
+                let mut len = (token_bytes.len() as u16).to_le_bytes().to_vec();
+                len.append(&mut token_bytes);
+
+                (0x03, len)
+            }
+
+            VariableData::RealMatrix(rows) => {
+                let column_count = rows.first().map_or(0, |row| row.len());
+
+                (
+                    0x02,
+                    [column_count as u8, rows.len() as u8]
+                        .into_iter()
+                        .chain(rows.into_iter().flat_map(|row| {
+                            row.into_iter()
+                                .flat_map(|element| float_to_tifloat(element).to_raw_bytes())
+                        }))
+                        .collect::<Vec<u8>>(),
+                )
+            }
+
             VariableData::RealList(list) => (
                 0x01,
                 (list.len() as u16)
@@ -131,23 +459,104 @@ impl From<Variable> for TIEntry {
                     .collect::<Vec<u8>>(),
             ),
 
+            VariableData::ComplexList(list) => (
+                0x0D,
+                (list.len() as u16)
+                    .to_le_bytes()
+                    .into_iter()
+                    .chain(
+                        list.into_iter()
+                            .flat_map(|element| complex_to_raw_bytes(element.re, element.im)),
+                    )
+                    .collect::<Vec<u8>>(),
+            ),
+
             VariableData::RealNumber(number) => {
                 let data = float_to_tifloat(number).to_raw_bytes();
                 (0x00, data.to_vec())
             }
+
+            VariableData::ComplexNumber(number) => {
+                let data = complex_to_raw_bytes(number.re, number.im);
+                (0x0C, data.to_vec())
+            }
         };
 
         TIEntry::new(name, file_type, data)
     }
 }
 
+impl TryFrom<&TIEntry> for VariableData {
+    type Error = TestError;
+
+    fn try_from(entry: &TIEntry) -> Result<Self, Self::Error> {
+        let file_type = entry.file_type & 0x1F;
+        let unreadable = || TestError::UnreadableVariable(file_type);
+
+        match file_type {
+            0x03 | 0x04 => {
+                let token_bytes = entry.data.get(2..).ok_or_else(unreadable)?;
+                let text = Tokens::from_bytes(token_bytes, None).to_string(tokenizer());
+
+                Ok(if file_type == 0x03 {
+                    VariableData::Equation { equation: text }
+                } else {
+                    VariableData::String(text)
+                })
+            }
+
+            0x00 | 0x01 | 0x02 | 0x0C | 0x0D => {
+                let (header, floats) = entry.numeric_parts().ok_or_else(unreadable)?;
+                let numbers = floats.iter().map(tifloat_to_float).collect::<Vec<_>>();
+                let complex_numbers = numbers
+                    .chunks_exact(2)
+                    .map(|pair| Complex {
+                        re: pair[0],
+                        im: pair[1],
+                    })
+                    .collect::<Vec<_>>();
+
+                match file_type {
+                    0x00 => numbers
+                        .first()
+                        .map(|&number| VariableData::RealNumber(number))
+                        .ok_or_else(unreadable),
+                    0x01 => Ok(VariableData::RealList(numbers)),
+                    0x02 => Ok(VariableData::RealMatrix(
+                        numbers
+                            .chunks(header[0].max(1) as usize)
+                            .map(|row| row.to_vec())
+                            .collect(),
+                    )),
+                    0x0C => complex_numbers
+                        .first()
+                        .map(|&number| VariableData::ComplexNumber(number))
+                        .ok_or_else(unreadable),
+                    _ => Ok(VariableData::ComplexList(complex_numbers)),
+                }
+            }
+
+            _ => Err(unreadable()),
+        }
+    }
+}
+
 impl From<Tokens> for TIEntry {
     fn from(value: Tokens) -> Self {
-        let mut token_bytes: Vec<u8> = value.into();
+        TIEntry::program(PROGRAM_NAME, value)
+    }
+}
+
+impl TIEntry {
+    fn program(name: &str, tokens: Tokens) -> Self {
+        let mut padded_name = [0u8; 8];
+        padded_name[..name.len()].copy_from_slice(name.as_bytes());
+
+        let mut token_bytes: Vec<u8> = tokens.into();
         let mut len = (token_bytes.len() as u16).to_le_bytes().to_vec();
         len.append(&mut token_bytes);
 
-        TIEntry::new(*b"TESTPROG", 0x05, len)
+        TIEntry::new(padded_name, 0x05, len)
     }
 }
 
@@ -183,7 +592,21 @@ pub struct AutotesterConfig {
     target: Program,
     sequence: Vec<String>,
     transfer_files: Vec<String>,
-    hashes: HashMap<String, ()>,
+    hashes: HashMap<String, Hash>,
+    /// The total of the sequence's delays.
+    #[serde(skip)]
+    delay_ms: u64,
+}
+
+/// A check of a region of the calculator's memory against its CRC-32.
+#[derive(Clone, Serialize)]
+struct Hash {
+    description: String,
+    /// An address or one of the autotester's names for one, eg. `0xD006C0`.
+    start: String,
+    size: String,
+    #[serde(rename = "expected_CRCs")]
+    expected_crcs: Vec<String>,
 }
 
 impl AutotesterConfig {
@@ -191,259 +614,1935 @@ impl AutotesterConfig {
         Self {
             rom: rom_path,
             target: Program {
-                name: "TESTPROG".to_owned(),
+                name: DRIVER_NAME.to_owned(),
                 is_asm: false,
             },
             sequence: vec![
                 "action|launch".to_owned(),
-                "delay|1500".to_owned(),
-                "key|on".to_owned(),
+                format!("delay|{}", LAUNCH_DELAY_MS),
             ],
             transfer_files: vec![],
             hashes: HashMap::new(),
+            delay_ms: LAUNCH_DELAY_MS.into(),
+        }
+    }
+
+    /// Runs the cases in turn, giving each `case_time` to run before its outputs are exported,
+    /// see [driver_program].
+    fn for_cases(
+        rom_path: String,
+        cases: &[TestCase],
+        case_time: Duration,
+    ) -> Result<Self, TestError> {
+        let mut autotester_config = AutotesterConfig::with_rom(rom_path);
+        let case_ms = case_time.as_millis() as u32;
+
+        for (index, case) in cases.iter().enumerate() {
+            autotester_config.add_delay(case_ms);
+
+            // by now the program is waiting for them, and it's given as long again to finish
+            if !case.keys.is_empty() {
+                for key in case.keys {
+                    autotester_config.add_key(key.name);
+                }
+                autotester_config.add_delay(case_ms);
+            }
+
+            if let Some(display) = case.display {
+                let screen = HomeScreen::showing(display).map_err(TestError::Display)?;
+                autotester_config.add_hash(
+                    &format!("case{} home screen", index),
+                    TEXT_SHADOW_ADDRESS,
+                    ROWS * COLUMNS,
+                    vec![format!("{:08X}", crc32(&screen.text_shadow()))],
+                );
+            }
+
+            if let Some(Graph::Crcs(crcs)) = case.graph {
+                autotester_config.add_hash(
+                    &format!("case{} graph screen", index),
+                    LCD_ADDRESS + (STATUS_BAR_ROWS * LCD_ROW_BYTES) as u32,
+                    (LCD_ROWS - STATUS_BAR_ROWS) * LCD_ROW_BYTES,
+                    crcs.clone(),
+                );
+            }
+
+            if let Some(error) = case.error {
+                let err_nos = match error {
+                    ExpectedError::Nothing => vec![0],
+                    ExpectedError::Error(kind) => {
+                        vec![kind.code(), kind.code() | ERR_NO_GOTO_FLAG]
+                    }
+                };
+
+                autotester_config.add_hash(
+                    &format!("case{} error", index),
+                    ERR_NO_ADDRESS,
+                    1,
+                    err_nos
+                        .into_iter()
+                        .map(|err_no| format!("{:08X}", crc32(&[err_no])))
+                        .collect(),
+                );
+            }
+
+            for output in case.outputs {
+                autotester_config.add_export(output.name());
+            }
+            if let Some(Graph::Like(_)) = case.graph {
+                autotester_config.add_export(&format!("Pic{}", GRAPH_PICTURE));
+            }
+
+            autotester_config.add_export(RUNNING_LIST);
+            autotester_config.add_export(DRIVER_NAME);
+            autotester_config.add_key("enter");
         }
+
+        Ok(autotester_config)
+    }
+
+    /// How long the autotester may take to get through the whole sequence.
+    fn time_limit(&self) -> Duration {
+        Duration::from_millis(self.delay_ms) + SESSION_OVERHEAD
     }
 
     pub fn add_import(&mut self, path: String) {
         self.transfer_files.push(path)
     }
 
+    pub fn add_delay(&mut self, milliseconds: u32) {
+        self.delay_ms += u64::from(milliseconds);
+        self.sequence.push(format!("delay|{}", milliseconds));
+    }
+
+    pub fn add_key(&mut self, key: &str) {
+        self.sequence.push(format!("key|{}", key));
+    }
+
     pub fn add_export(&mut self, var_name: &str) {
         self.sequence
             .push(format!("saveVar|{}", translate_variable_name(var_name)));
     }
-}
-
-/// translate from the token sheets' accessible name into CEmu's preferred name for the variable.
-fn translate_variable_name(var_name: &str) -> &str {
-    match var_name {
-        "L1" => "L\u{2081}",
-        "L2" => "L\u{2082}",
-        "L3" => "L\u{2083}",
-        "L4" => "L\u{2084}",
-        "L5" => "L\u{2085}",
-        "L6" => "L\u{2086}",
-
-        "theta" => "\u{03B8}",
 
-        name => name,
+    /// Checks that `size` bytes of memory from `start` have one of the expected CRC-32s, given
+    /// in hex. The autotester exits with the number of checks which failed.
+    pub fn add_hash(&mut self, name: &str, start: u32, size: usize, expected_crcs: Vec<String>) {
+        self.hashes.insert(
+            name.to_owned(),
+            Hash {
+                description: name.to_owned(),
+                start: format!("{:#X}", start),
+                size: size.to_string(),
+                expected_crcs,
+            },
+        );
+        self.sequence.push(format!("hash|{}", name));
     }
 }
 
-pub struct TestRunner {
-    rom_path: Option<PathBuf>,
-}
+/// The program launched by the autotester. It sets up and runs each case in turn, pausing after
+/// each one so that its outputs can be exported.
+fn driver_program(program: &Tokens, cases: &[TestCase]) -> Tokens {
+    let resets = reset_lines(program, cases);
+    let mut lines = vec![];
 
-impl TestRunner {
-    pub fn new() -> Self {
-        TestRunner { rom_path: None }
-    }
+    for case in cases {
+        // anything left over from the previous case shouldn't be able to affect this one
+        lines.extend(resets.iter().cloned());
+        if let Some(Graph::Like(_)) = case.graph {
+            lines.push(format!("DelVar Pic{}", GRAPH_PICTURE));
+        }
 
-    pub fn find_rom(&mut self) -> Result<String, TestError> {
-        if let Some(pathbuf) = &self.rom_path {
-            if !fs::exists(pathbuf).map_err(TestError::Io)? {
-                self.rom_path = None;
-            } else {
-                return Ok(pathbuf.to_str().unwrap().to_owned());
-            }
+        if let Some(seed) = case.seed {
+            lines.push(format!("{}->rand", seed));
         }
 
-        let paths =
-            fs::read_dir(env::current_dir().map_err(TestError::Io)?).map_err(TestError::Io)?;
+        if case.display.is_some() {
+            lines.push("ClrHome".to_owned());
+        }
+        if case.graph.is_some() {
+            lines.push("ClrDraw".to_owned());
+        }
 
-        for entry in paths {
-            let entry = entry.map_err(TestError::Io)?;
-            if entry.file_name().to_string_lossy().ends_with(".rom") {
-                self.rom_path = Some(entry.path().canonicalize().map_err(TestError::Io)?);
-                return Ok(self.rom_path.as_ref().unwrap().to_str().unwrap().to_owned());
-            }
+        lines.push(format!("{{1->{}", RUNNING_LIST));
+        // this sets Ans, so nothing after it can be allowed to change it
+        lines.extend(input_assignments(case.inputs));
+
+        lines.push(format!("prgm{}", PROGRAM_NAME));
+        lines.push(format!("DelVar {}", RUNNING_LIST));
+
+        // the graph screen stays up while paused, so that it can be hashed
+        match case.graph {
+            Some(Graph::Crcs(_)) => lines.push("DispGraph".to_owned()),
+            Some(Graph::Like(_)) => lines.push(format!("StorePic {}", GRAPH_PICTURE)),
+            None => {}
         }
+        lines.push("Pause ".to_owned());
+    }
 
-        Err(TestError::NoRom)
+    tokenize(&lines.join("\n"))
+}
+
+/// Clears every variable which the program or any of the cases use, so that each case starts
+/// out as if it had the calculator to itself. Inputs which have to be transferred are left alone,
+/// since they're only transferred once.
+fn reset_lines(program: &Tokens, cases: &[TestCase]) -> Vec<String> {
+    let mut names = variable_names(&program.clone().collect::<Vec<_>>());
+    for case in cases {
+        names.extend(case.inputs.iter().map(|input| input.name.clone()));
+        names.extend(case.outputs.iter().map(|output| output.name().to_owned()));
     }
 
-    fn run_cemu_test(
-        &mut self,
-        program: Tokens,
-        inputs: &Vec<Variable>,
-        outputs: &Vec<Variable>,
-    ) -> Result<ProgramTestResult, TestError> {
-        let folder = tempfile::tempdir().map_err(TestError::Io)?;
-        let folder_path = folder.path();
+    let transferred = cases
+        .iter()
+        .flat_map(|case| case.inputs)
+        .filter(|input| variable_literal(&input.value).is_none())
+        .map(|input| &input.name)
+        .collect::<Vec<_>>();
 
-        let autotester_config_path =
-            self.initialize_cemu_test(folder_path, program, inputs, outputs)?;
+    names
+        .iter()
+        .filter(|name| *name != "Ans" && !transferred.contains(name))
+        .map(|name| match name.as_str() {
+            // these always exist, so they're emptied rather than deleted
+            "L1" | "L2" | "L3" | "L4" | "L5" | "L6" => format!("ClrList {}", name),
+            _ => format!("DelVar {}", name),
+        })
+        .collect()
+}
 
-        let autotester_path =
-            env::current_dir()
-                .map_err(TestError::Io)?
-                .join(if cfg!(target_os = "windows") {
-                    "autotester.exe"
-                } else {
-                    "autotester"
-                });
+/// The program launched by the autotester for a speed test. Once the clock ticks over, it runs
+/// the program `speed.runs` times, setting the inputs before each run, and then appends the
+/// whole seconds that took to [TIMER_LIST].
+///
+/// The clock only counts whole seconds, so the driver then counts how many times it can loop
+/// before the clock ticks over again, and how many times in the whole second after that. The
+/// first count is the part of a second the runs didn't take, see [timed_seconds].
+fn timing_program(speed: &Speed) -> Tokens {
+    let mut lines = vec![
+        format!("{{startTmr->{}", TIMER_LIST),
+        format!("Repeat checkTmr({}(1", TIMER_LIST),
+        "End".to_owned(),
+        format!("{{startTmr->{}", TIMER_LIST),
+    ];
 
-        let cemu_status = Command::new(autotester_path)
-            .arg(&autotester_config_path)
-            .current_dir(folder_path)
-            .stdout(Stdio::null())
-            .spawn()
-            .expect("Failed to start CEmu process.")
-            .wait()
-            .map_err(TestError::Io)?;
+    let inputs = input_assignments(&speed.input);
+    for _ in 0..speed.runs {
+        lines.extend(inputs.iter().cloned());
+        lines.push(format!("prgm{}", PROGRAM_NAME));
+    }
 
-        self.validate_cemu_test_state(
-            cemu_status,
-            &folder_path.canonicalize().map_err(TestError::Io)?,
-            outputs,
-        )
+    lines.push(format!("checkTmr({}(1->{}(2", TIMER_LIST, TIMER_LIST));
+    // counting until the clock ticks over, then until it ticks over again
+    for (index, ticks) in [(3, ""), (4, "1+")] {
+        lines.extend([
+            format!("0->{}({}", TIMER_LIST, index),
+            format!(
+                "Repeat checkTmr({}(1))>{}{}(2",
+                TIMER_LIST, ticks, TIMER_LIST
+            ),
+            format!("{}({})+1->{}({}", TIMER_LIST, index, TIMER_LIST, index),
+            "End".to_owned(),
+        ]);
     }
+    lines.push("Pause ".to_owned());
 
-    /// Sets up variables, autotester config, etc
-    fn initialize_cemu_test(
-        &mut self,
-        folder: &Path,
-        program: Tokens,
-        inputs: &Vec<Variable>,
-        outputs: &Vec<Variable>,
-    ) -> Result<PathBuf, TestError> {
-        let autotester_config_path = folder.join("autotester.json");
-        let mut autotester_config = AutotesterConfig::with_rom(self.find_rom()?);
-        for input in inputs {
-            let destination_path =
-                folder.join(input.name.clone() + "." + input.value.file_extension());
+    tokenize(&lines.join("\n"))
+}
 
-            autotester_config.add_import(destination_path.to_str().unwrap().to_owned());
+/// The seconds a speed test's runs took, from the [TIMER_LIST] left by [timing_program], or `None`
+/// if they weren't done.
+fn timed_seconds(times: &[f64]) -> Option<f64> {
+    match times {
+        [_, whole_seconds, rest, second] => Some(whole_seconds + 1.0 - rest / second),
+        _ => None,
+    }
+}
 
-            let entry: TIEntry = (*input).clone().into();
-            let file: TIFile = entry.into();
+/// Stores each input which can be written as an expression, eg. `{1,2,3}->L1`. The rest are
+/// transferred before the session starts.
+///
+/// `Ans` can't be stored to, so the last line sets it by evaluating its value, or `0` if it's not
+/// an input.
+fn input_assignments(inputs: &[Variable]) -> Vec<String> {
+    let (ans, variables): (Vec<_>, Vec<_>) = inputs.iter().partition(|input| input.name == "Ans");
 
-            fs::write(
-                destination_path,
-                file.to_bytes().map_err(TestError::TIFileParsing)?,
-            )
-            .map_err(TestError::Io)?;
-        }
+    let mut lines = variables
+        .into_iter()
+        .filter_map(|input| {
+            Some(format!(
+                "{}->{}",
+                variable_literal(&input.value)?,
+                input.name
+            ))
+        })
+        .collect::<Vec<_>>();
 
-        for output in outputs {
-            autotester_config.add_export(&output.name);
-        }
+    lines.push(
+        ans.first()
+            .and_then(|input| variable_literal(&input.value))
+            .unwrap_or_else(|| "0".to_owned()),
+    );
 
-        let program_path = folder.join("TESTPROG.8xp");
-        autotester_config.add_import(program_path.to_str().unwrap().to_owned());
-        let program_file = fs::File::create(program_path).map_err(TestError::Io)?;
-        let entry: TIEntry = program.into();
-        let file: TIFile = entry.into();
-        file.to_writer(&mut Writer::new(program_file), ())
-            .map_err(TestError::TIFileParsing)?;
+    lines
+}
 
-        serde_json::to_writer(
-            fs::File::create(&autotester_config_path).map_err(TestError::Io)?,
-            &autotester_config,
+/// TI-BASIC which evaluates to `value`, if it can be written as an expression.
+fn variable_literal(value: &VariableData) -> Option<String> {
+    fn join(items: impl Iterator<Item = String>) -> String {
+        items.collect::<Vec<_>>().join(",")
+    }
+
+    fn complex_literal(number: &Complex) -> String {
+        format!(
+            "({}+{}[i])",
+            float_literal(number.re),
+            float_literal(number.im)
         )
-        .unwrap();
+    }
 
-        Ok(autotester_config_path)
+    match value {
+        VariableData::String(text) | VariableData::Equation { equation: text } => {
+            // there's no way to write these inside a string literal
+            if text.contains('"') || text.contains("->") {
+                None
+            } else {
+                Some(format!("\"{}\"", text))
+            }
+        }
+        VariableData::RealMatrix(rows) => Some(format!(
+            "[{}]",
+            rows.iter()
+                .map(|row| format!("[{}]", join(row.iter().map(|&x| float_literal(x)))))
+                .collect::<String>()
+        )),
+        VariableData::RealList(list) => Some(format!(
+            "{{{}}}",
+            join(list.iter().map(|&x| float_literal(x)))
+        )),
+        VariableData::ComplexList(list) => {
+            Some(format!("{{{}}}", join(list.iter().map(complex_literal))))
+        }
+        VariableData::RealNumber(number) => Some(float_literal(*number)),
+        VariableData::ComplexNumber(number) => Some(complex_literal(number)),
     }
+}
 
-    fn validate_cemu_test_state(
-        &self,
-        cemu_status: ExitStatus,
-        folder: &Path,
-        outputs: &Vec<Variable>,
-    ) -> Result<ProgramTestResult, TestError> {
-        if !cemu_status.success() {
-            Err(TestError::CEmuCrashed(cemu_status))
-        } else {
-            for output in outputs {
-                let variable_name = output.name.clone();
-
-                let actual_path = folder.join(format!(
-                    "{}.{}",
-                    translate_variable_name(&variable_name),
-                    output.value.file_extension()
-                ));
-
-                if !fs::exists(&actual_path).map_err(TestError::Io)? {
-                    if output.name == "Ans" {
-                        return Ok(ProgramTestResult::Fail("Cannot find Ans; perhaps Ans is the wrong type at the end of your program.".to_owned()));
-                    }
+/// Sorts the variables exported by the autotester into a folder per case.
+///
+/// Every case's outputs are exported under the same file names, so they have to be moved out of
+/// the way before the next case's are saved. The driver program is exported after each case's
+/// outputs to mark them as complete.
+struct ExportCollector {
+    exports: PathBuf,
+    root: PathBuf,
+    completed_cases: usize,
+}
 
-                    return Ok(ProgramTestResult::Fail(format!(
-                        "Cannot find variable {}.",
-                        variable_name
-                    )));
-                }
+impl ExportCollector {
+    fn new(root: &Path) -> Result<Self, TestError> {
+        let exports = root.join("exports");
+        fs::create_dir(&exports).map_err(TestError::Io)?;
 
-                let actual = TIFile::from_reader((
-                    &mut fs::File::options()
-                        .read(true)
-                        .open(actual_path)
-                        .map_err(TestError::Io)?,
-                    0,
-                ))
-                .map_err(TestError::TIFileParsing)?
-                .1
-                .entry;
+        Ok(ExportCollector {
+            exports,
+            root: root.to_owned(),
+            completed_cases: 0,
+        })
+    }
 
-                let expected: TIEntry = output.clone().into();
+    fn case_folder(&self, index: usize) -> PathBuf {
+        self.root.join(format!("case{}", index))
+    }
 
-                if expected.data != actual.data {
-                    return Ok(ProgramTestResult::Fail(format!(
-                        "Incorrect value for variable {}.",
-                        variable_name
-                    )));
-                }
-            }
+    fn collect(&mut self) -> Result<(), TestError> {
+        let marker_path = self.exports.join(DRIVER_NAME.to_owned() + ".8xp");
+        if !fs::exists(&marker_path).map_err(TestError::Io)? {
+            return Ok(());
+        }
 
-            Ok(ProgramTestResult::Pass)
+        // the autotester may not have finished writing it yet
+        let marker = fs::read(&marker_path).map_err(TestError::Io)?;
+        if TIFile::from_bytes((&marker, 0)).is_err() {
+            return Ok(());
         }
-    }
 
-    /// Test program.
-    ///
-    /// If any of the root tests pass, the program passes.
-    /// All of the tests in a test group must pass for the whole group to pass.
-    pub fn run_tests(
-        &mut self,
-        program: Tokens,
-        tests: &Vec<Test>,
-    ) -> Result<ProgramTestResult, TestError> {
-        let mut last_result = ProgramTestResult::Fail("Tests failed.".to_owned());
-        for test in tests {
-            last_result = self.run_test(program.clone(), test)?;
-            if let ProgramTestResult::Pass = last_result {
-                return Ok(ProgramTestResult::Pass);
-            }
+        let case_folder = self.case_folder(self.completed_cases);
+        fs::create_dir(&case_folder).map_err(TestError::Io)?;
+
+        for entry in fs::read_dir(&self.exports).map_err(TestError::Io)? {
+            let entry = entry.map_err(TestError::Io)?;
+            fs::rename(entry.path(), case_folder.join(entry.file_name())).map_err(TestError::Io)?;
         }
 
-        Ok(last_result)
+        self.completed_cases += 1;
+
+        Ok(())
     }
+}
 
-    fn run_test(&mut self, program: Tokens, test: &Test) -> Result<ProgramTestResult, TestError> {
-        match test {
-            Test::CEmu { input, output } => self.run_cemu_test(program, input, output),
-            Test::FulltextMatch { regex } => {
-                let regex_result = Regex::new(&("^".to_owned() + regex + "$"))
+/// Splits a case so that each part has at most one of the checks which the autotester hashes: the
+/// home screen, the graph screen's CRC and the error. Only the first part checks the outputs and
+/// the graph screen's picture.
+fn hashed_parts<'a>(case: &TestCase<'a>) -> Vec<TestCase<'a>> {
+    let unhashed = TestCase {
+        display: None,
+        graph: case.graph.filter(|graph| matches!(graph, Graph::Like(_))),
+        error: None,
+        ..*case
+    };
+
+    let mut parts = vec![];
+    if case.display.is_some() {
+        parts.push(TestCase {
+            display: case.display,
+            ..unhashed
+        });
+    }
+    if let Some(graph @ Graph::Crcs(_)) = case.graph {
+        parts.push(TestCase {
+            graph: Some(graph),
+            ..unhashed
+        });
+    }
+    if case.error.is_some() {
+        parts.push(TestCase {
+            error: case.error,
+            ..unhashed
+        });
+    }
+
+    if parts.is_empty() {
+        parts.push(unhashed);
+    }
+    for part in parts.iter_mut().skip(1) {
+        part.outputs = &[];
+        if let Some(Graph::Like(_)) = part.graph {
+            part.graph = None;
+        }
+    }
+
+    parts
+}
+
+/// Combines the outcomes of a case's [hashed_parts], each of which only knows about its own
+/// checks.
+fn merge_outcomes(first: CaseOutcome, other: CaseOutcome) -> CaseOutcome {
+    match (first, other) {
+        (
+            CaseOutcome::Finished {
+                exports,
+                screen,
+                graph,
+                ending,
+            },
+            CaseOutcome::Finished {
+                screen: other_screen,
+                graph: other_graph,
+                ending: other_ending,
+                ..
+            },
+        ) => CaseOutcome::Finished {
+            exports,
+            screen: screen.or(other_screen),
+            graph: graph.or(other_graph),
+            ending: match ending {
+                Ending::Unknown(None) => other_ending,
+                ending => ending,
+            },
+        },
+        (CaseOutcome::TimedOut(ran), _) | (_, CaseOutcome::TimedOut(ran)) => {
+            CaseOutcome::TimedOut(ran)
+        }
+    }
+}
+
+/// Whether the program can end the driver along with itself.
+fn stops(program: &Tokens) -> bool {
+    let stop = tokenize("Stop").next();
+    program.clone().any(|token| Some(token) == stop)
+}
+
+/// eg. "A=1, B=2", for pointing out which of a test's input sets failed.
+fn describe_case(case: &TestCase) -> String {
+    let mut conditions = case
+        .inputs
+        .iter()
+        .map(|input| format!("{}={}", input.name, input.value))
+        .collect::<Vec<_>>();
+
+    if let Some(seed) = case.seed {
+        conditions.push(format!("rand is seeded with {}", seed));
+    }
+
+    if !case.keys.is_empty() {
+        conditions.push(format!(
+            "the keys pressed are {}",
+            case.keys
+                .iter()
+                .map(|key| key.to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        ));
+    }
+
+    if conditions.is_empty() {
+        return "there are no inputs".to_owned();
+    }
+
+    conditions.join(", ")
+}
+
+/// translate from the token sheets' accessible name into CEmu's preferred name for the variable.
+fn translate_variable_name(var_name: &str) -> String {
+    // CEmu names custom lists without the ʟ
+    if let Some(list_name) = var_name.strip_prefix(CUSTOM_LIST_PREFIX) {
+        return list_name.to_owned();
+    }
+
+    // equation variables are written with subscripts, eg. {X1T} is X₁ᴛ
+    if let Some(equation_name) = var_name
+        .strip_prefix('{')
+        .and_then(|name| name.strip_suffix('}'))
+    {
+        return equation_name
+            .chars()
+            .map(|c| match c {
+                '0'..='9' => char::from_u32(0x2080 + c.to_digit(10).unwrap()).unwrap(),
+                'T' => '\u{1D1B}',
+                c => c,
+            })
+            .collect();
+    }
+
+    match var_name {
+        "L1" => "L\u{2081}",
+        "L2" => "L\u{2082}",
+        "L3" => "L\u{2083}",
+        "L4" => "L\u{2084}",
+        "L5" => "L\u{2085}",
+        "L6" => "L\u{2086}",
+
+        "theta" => "\u{03B8}",
+
+        name => name,
+    }
+    .to_owned()
+}
+
+/// One set of inputs and the outputs the program should leave behind for them.
+#[derive(Clone, Copy, Debug)]
+pub struct TestCase<'a> {
+    pub inputs: &'a [Variable],
+    pub outputs: &'a [Output],
+    /// Stored to `rand` before the program runs.
+    pub seed: Option<u64>,
+    /// The home screen the program should leave behind, after starting from a clear one.
+    pub display: Option<&'a [DisplayLine]>,
+    pub graph: Option<&'a Graph>,
+    pub error: Option<ExpectedError>,
+    /// Pressed once the program is running.
+    pub keys: &'a [&'static Key],
+}
+
+impl<'a> TestCase<'a> {
+    /// The names of the outputs whose expected values come from the starting program.
+    fn generated_outputs(&self) -> impl Iterator<Item = &'a String> {
+        self.outputs.iter().filter_map(|output| match output {
+            Output::Generated(name) => Some(name),
+            Output::Expected(_) => None,
+        })
+    }
+
+    /// The cases of a CEmu test, one for each of its seeds.
+    pub fn of(test: &'a Test) -> Vec<Self> {
+        let Test::CEmu {
+            input,
+            output,
+            display,
+            graph,
+            error,
+            keys,
+            seeds,
+        } = test
+        else {
+            return vec![];
+        };
+
+        let case = |seed| TestCase {
+            inputs: input,
+            outputs: output,
+            seed,
+            display: display.as_deref(),
+            graph: graph.as_ref(),
+            error: *error,
+            keys,
+        };
+
+        if seeds.is_empty() {
+            vec![case(None)]
+        } else {
+            seeds.iter().map(|&seed| case(Some(seed))).collect()
+        }
+    }
+}
+
+/// Everything which can change what a program does in a case: its inputs, seed and keys.
+#[derive(PartialEq, Eq, Hash)]
+struct CaseKey {
+    inputs: Vec<(String, String)>,
+    seed: Option<u64>,
+    keys: Vec<&'static str>,
+}
+
+impl CaseKey {
+    fn of(case: &TestCase) -> Self {
+        CaseKey {
+            inputs: case
+                .inputs
+                .iter()
+                .map(|input| (input.name.clone(), format!("{:?}", input.value)))
+                .collect(),
+            seed: case.seed,
+            keys: case.keys.iter().map(|key| key.name).collect(),
+        }
+    }
+}
+
+/// What a backend found once a case had run.
+pub enum CaseOutcome {
+    Finished {
+        /// Each of the case's outputs which the program left behind, by name. Outputs with an
+        /// expected value are left out unless they have the expected type.
+        exports: HashMap<String, TIEntry>,
+        /// Only checked if the case has an expected display.
+        screen: Option<ScreenCheck>,
+        /// Only checked if the case has an expected graph.
+        graph: Option<GraphCheck>,
+        ending: Ending,
+    },
+    /// The program was stopped after running for this long.
+    TimedOut(Duration),
+}
+
+pub enum ScreenCheck {
+    Matches,
+    /// The lines that were on the screen instead, if the backend can read them.
+    Differs(Option<Vec<String>>),
+}
+
+/// How the program ended.
+pub enum Ending {
+    Completed,
+    Stopped(ErrorKind),
+    /// CEmu can only tell whether the program ended as the case expected, if it expects
+    /// anything.
+    Unknown(Option<bool>),
+}
+
+pub enum GraphCheck {
+    /// Whether the LCD had one of the expected CRCs.
+    Crc(bool),
+    /// The data of the graph screen's picture, if it could be saved, to be compared against the
+    /// reference program's.
+    Picture(Option<Vec<u8>>),
+}
+
+/// Somewhere to run programs against test cases.
+pub trait TestBackend {
+    /// Runs `program` for each case, giving up on any case still running after `timeout`.
+    fn run_cases(
+        &mut self,
+        program: &Tokens,
+        cases: &[TestCase],
+        timeout: Duration,
+    ) -> Result<Vec<CaseOutcome>, TestError>;
+
+    /// How many seconds `speed.runs` runs of `program` take on the calculator, or `None` if they
+    /// aren't done within `speed.seconds`.
+    fn time_runs(
+        &mut self,
+        program: &Tokens,
+        speed: &Speed,
+        timeout: Duration,
+    ) -> Result<Option<f64>, TestError>;
+
+    /// Whether cases can expect a graph screen.
+    fn checks_graphs(&self) -> bool;
+}
+
+/// Runs programs on the real calculator OS in CEmu's autotester. This needs a ROM, and is always
+/// used when there is one.
+#[derive(Default)]
+pub struct CEmuBackend {
+    rom_path: Option<PathBuf>,
+}
+
+impl CEmuBackend {
+    pub fn find_rom(&mut self) -> Result<String, TestError> {
+        if let Some(pathbuf) = &self.rom_path {
+            if !fs::exists(pathbuf).map_err(TestError::Io)? {
+                self.rom_path = None;
+            } else {
+                return Ok(pathbuf.to_str().unwrap().to_owned());
+            }
+        }
+
+        let paths =
+            fs::read_dir(env::current_dir().map_err(TestError::Io)?).map_err(TestError::Io)?;
+
+        for entry in paths {
+            let entry = entry.map_err(TestError::Io)?;
+            if entry.file_name().to_string_lossy().ends_with(".rom") {
+                self.rom_path = Some(entry.path().canonicalize().map_err(TestError::Io)?);
+                return Ok(self.rom_path.as_ref().unwrap().to_str().unwrap().to_owned());
+            }
+        }
+
+        Err(TestError::NoRom)
+    }
+
+    fn autotester_path() -> Result<PathBuf, TestError> {
+        Ok(env::current_dir()
+            .map_err(TestError::Io)?
+            .join(if cfg!(target_os = "windows") {
+                "autotester.exe"
+            } else {
+                "autotester"
+            }))
+    }
+
+    /// Whether there's both a ROM and the autotester to run it with.
+    pub fn is_available(&mut self) -> bool {
+        self.find_rom().is_ok() && Self::autotester_path().is_ok_and(|path| path.exists())
+    }
+
+    /// Runs the cases in order, giving each `case_time` before its outputs are exported, and
+    /// returns the outcomes of those the driver got through. A case which is still running after
+    /// `case_time` is run again on its own with the whole `timeout`, and its outcome is the last.
+    fn run_session(
+        &mut self,
+        program: &Tokens,
+        cases: &[TestCase],
+        case_time: Duration,
+        timeout: Duration,
+    ) -> Result<Vec<CaseOutcome>, TestError> {
+        let folder = tempfile::tempdir().map_err(TestError::Io)?;
+        let folder_path = folder.path().canonicalize().map_err(TestError::Io)?;
+
+        let (autotester_config_path, time_limit) =
+            self.initialize_session(&folder_path, program.clone(), cases, case_time)?;
+        let mut exports = ExportCollector::new(&folder_path)?;
+        let cemu_status = Self::run_autotester(&autotester_config_path, &mut exports, time_limit)?;
+
+        // the autotester exits with the number of hashes which didn't match
+        let screen_checks = cases
+            .iter()
+            .map(|case| {
+                case.display.is_some() as i32
+                    + matches!(case.graph, Some(Graph::Crcs(_))) as i32
+                    + case.error.is_some() as i32
+            })
+            .sum::<i32>();
+        let failed_screen_checks = match cemu_status.map(|status| (status, status.code())) {
+            Some((_, Some(code))) if (0..=screen_checks).contains(&code) => code,
+            Some((status, _)) => return Err(TestError::CEmuCrashed(status)),
+            None => 0,
+        };
+
+        let mut outcomes = vec![];
+        for (index, case) in cases.iter().enumerate() {
+            // if the session timed out, only the cases whose outputs were exported actually finished
+            if index >= exports.completed_cases && cemu_status.is_none() {
+                outcomes.push(CaseOutcome::TimedOut(timeout));
+                continue;
+            }
+
+            let folder = exports.case_folder(index);
+
+            // sessions with a hashed check have no other cases or checks, so any failure is its
+            let screen = case.display.map(|_| match failed_screen_checks {
+                0 => ScreenCheck::Matches,
+                _ => ScreenCheck::Differs(None),
+            });
+            let graph = match case.graph {
+                Some(Graph::Crcs(_)) => Some(GraphCheck::Crc(failed_screen_checks == 0)),
+                Some(Graph::Like(_)) => Some(GraphCheck::Picture(Self::read_picture(&folder)?)),
+                None => None,
+            };
+
+            let ending = Ending::Unknown(case.error.map(|_| failed_screen_checks == 0));
+
+            let finished = CaseOutcome::Finished {
+                exports: Self::read_exports(&folder, case.outputs)?,
+                screen,
+                graph,
+                ending,
+            };
+
+            // a program which can stop itself would take the driver with it, and a case which
+            // stopped with the error it expected never returns to the driver anyway
+            let running = !stops(program)
+                && folder
+                    .join(translate_variable_name(RUNNING_LIST) + ".8xl")
+                    .exists();
+            let stopped_as_expected =
+                matches!(case.error, Some(ExpectedError::Error(_))) && failed_screen_checks == 0;
+            if !running || stopped_as_expected {
+                outcomes.push(finished);
+                continue;
+            }
+
+            // otherwise it either stopped with an error or is still running, and in both cases
+            // the driver won't get any further
+            if case_time < timeout {
+                outcomes.extend(self.run_session(
+                    program,
+                    std::slice::from_ref(case),
+                    timeout,
+                    timeout,
+                )?);
+                break;
+            }
+
+            // a program which hasn't stopped with an error is still running
+            if matches!(case.error, Some(ExpectedError::Nothing)) {
+                outcomes.push(if failed_screen_checks == 0 {
+                    CaseOutcome::TimedOut(timeout)
+                } else {
+                    finished
+                });
+                break;
+            }
+
+            let check = TestCase {
+                display: None,
+                graph: None,
+                error: Some(ExpectedError::Nothing),
+                ..*case
+            };
+            let stopped_with_error = matches!(
+                self.run_session(program, &[check], timeout, timeout)?[..],
+                [CaseOutcome::Finished {
+                    ending: Ending::Unknown(Some(false)),
+                    ..
+                }]
+            );
+
+            outcomes.push(if stopped_with_error {
+                finished
+            } else {
+                CaseOutcome::TimedOut(timeout)
+            });
+            break;
+        }
+
+        Ok(outcomes)
+    }
+
+    /// Times the speed test's runs in a session of their own.
+    fn time_session(&mut self, program: &Tokens, speed: &Speed) -> Result<Option<f64>, TestError> {
+        let folder = tempfile::tempdir().map_err(TestError::Io)?;
+        let folder_path = folder.path().canonicalize().map_err(TestError::Io)?;
+
+        let (autotester_config_path, time_limit) =
+            self.initialize_timing_session(&folder_path, program.clone(), speed)?;
+        let mut exports = ExportCollector::new(&folder_path)?;
+
+        match Self::run_autotester(&autotester_config_path, &mut exports, time_limit)? {
+            Some(status) if status.success() => {}
+            Some(status) => return Err(TestError::CEmuCrashed(status)),
+            None => return Ok(None),
+        }
+
+        let timer = Output::Generated(TIMER_LIST.to_owned());
+        let exports = Self::read_exports(&exports.case_folder(0), std::slice::from_ref(&timer))?;
+
+        let times = exports
+            .get(TIMER_LIST)
+            .map(VariableData::try_from)
+            .transpose()?;
+
+        Ok(match times {
+            Some(VariableData::RealList(times)) => timed_seconds(&times),
+            _ => None,
+        })
+    }
+
+    /// Runs the autotester until it exits, returning `None` if it's still running after
+    /// `time_limit`.
+    fn run_autotester(
+        autotester_config_path: &Path,
+        exports: &mut ExportCollector,
+        time_limit: Duration,
+    ) -> Result<Option<ExitStatus>, TestError> {
+        let mut cemu_process = Command::new(Self::autotester_path()?)
+            .arg(autotester_config_path)
+            .current_dir(&exports.exports)
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("Failed to start CEmu process.");
+
+        let waited = Self::wait_for_cemu(&mut cemu_process, exports, time_limit);
+        if !matches!(waited, Ok(Some(_))) {
+            // don't leave the emulator running in the background, or holding on to the folder
+            let _ = cemu_process.kill();
+            let _ = cemu_process.wait();
+        }
+
+        waited
+    }
+
+    /// Returns `None` if the autotester is still running after `time_limit`.
+    fn wait_for_cemu(
+        cemu_process: &mut Child,
+        exports: &mut ExportCollector,
+        time_limit: Duration,
+    ) -> Result<Option<ExitStatus>, TestError> {
+        let start = Instant::now();
+
+        let cemu_status = loop {
+            exports.collect()?;
+
+            if let Some(status) = cemu_process.try_wait().map_err(TestError::Io)? {
+                break status;
+            }
+
+            if start.elapsed() > time_limit {
+                return Ok(None);
+            }
+
+            thread::sleep(POLL_INTERVAL);
+        };
+        exports.collect()?;
+
+        Ok(Some(cemu_status))
+    }
+
+    /// Sets up variables, programs, autotester config, etc, returning the config's path and how
+    /// long the session may take.
+    fn initialize_session(
+        &mut self,
+        folder: &Path,
+        program: Tokens,
+        cases: &[TestCase],
+        case_time: Duration,
+    ) -> Result<(PathBuf, Duration), TestError> {
+        let mut autotester_config =
+            AutotesterConfig::for_cases(self.find_rom()?, cases, case_time)?;
+        Self::import_inputs(
+            folder,
+            &mut autotester_config,
+            cases.iter().flat_map(|case| case.inputs),
+        )?;
+
+        let driver = driver_program(&program, cases);
+        Self::import_programs(folder, autotester_config, program, driver)
+    }
+
+    /// Sets up a session which runs the program `speed.runs` times, timing it with the
+    /// calculator's clock, see [timing_program].
+    fn initialize_timing_session(
+        &mut self,
+        folder: &Path,
+        program: Tokens,
+        speed: &Speed,
+    ) -> Result<(PathBuf, Duration), TestError> {
+        let mut autotester_config = AutotesterConfig::with_rom(self.find_rom()?);
+        Self::import_inputs(folder, &mut autotester_config, &speed.input)?;
+
+        // the driver waits for the clock to tick over before it starts timing
+        autotester_config.add_delay(speed.seconds * 1000 + TIMER_SYNC_MS + TIMER_FRACTION_MS);
+        autotester_config.add_export(TIMER_LIST);
+        autotester_config.add_export(DRIVER_NAME);
+        autotester_config.add_key("enter");
+
+        Self::import_programs(folder, autotester_config, program, timing_program(speed))
+    }
+
+    /// Transfers the inputs which the driver can't write itself.
+    fn import_inputs<'a>(
+        folder: &Path,
+        autotester_config: &mut AutotesterConfig,
+        inputs: impl IntoIterator<Item = &'a Variable>,
+    ) -> Result<(), TestError> {
+        let untypeable_inputs = inputs
+            .into_iter()
+            .filter(|input| variable_literal(&input.value).is_none());
+
+        for input in untypeable_inputs {
+            let destination_path = folder
+                .join(translate_variable_name(&input.name) + "." + input.value.file_extension());
+
+            autotester_config.add_import(destination_path.to_str().unwrap().to_owned());
+
+            let entry: TIEntry = input.clone().into();
+            let file: TIFile = entry.into();
+
+            fs::write(
+                destination_path,
+                file.to_bytes().map_err(TestError::TIFileParsing)?,
+            )
+            .map_err(TestError::Io)?;
+        }
+
+        Ok(())
+    }
+
+    /// Transfers the program and its driver, then writes the finished autotester config, returning
+    /// its path and how long the session may take.
+    fn import_programs(
+        folder: &Path,
+        mut autotester_config: AutotesterConfig,
+        program: Tokens,
+        driver: Tokens,
+    ) -> Result<(PathBuf, Duration), TestError> {
+        for (name, tokens) in [(PROGRAM_NAME, program), (DRIVER_NAME, driver)] {
+            let program_path = folder.join(name.to_owned() + ".8xp");
+            autotester_config.add_import(program_path.to_str().unwrap().to_owned());
+            let program_file = fs::File::create(program_path).map_err(TestError::Io)?;
+            let file: TIFile = TIEntry::program(name, tokens).into();
+            file.to_writer(&mut Writer::new(program_file), ())
+                .map_err(TestError::TIFileParsing)?;
+        }
+
+        let autotester_config_path = folder.join("autotester.json");
+        serde_json::to_writer(
+            fs::File::create(&autotester_config_path).map_err(TestError::Io)?,
+            &autotester_config,
+        )
+        .unwrap();
+
+        Ok((autotester_config_path, autotester_config.time_limit()))
+    }
+
+    fn read_entry(path: &Path) -> Result<TIEntry, TestError> {
+        Ok(TIFile::from_reader((
+            &mut fs::File::options()
+                .read(true)
+                .open(path)
+                .map_err(TestError::Io)?,
+            0,
+        ))
+        .map_err(TestError::TIFileParsing)?
+        .1
+        .entry)
+    }
+
+    /// The data of the graph screen's picture exported into a case's folder, if it's there.
+    fn read_picture(folder: &Path) -> Result<Option<Vec<u8>>, TestError> {
+        let path = folder.join(format!("Pic{}.8xi", GRAPH_PICTURE));
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        Ok(Some(Self::read_entry(&path)?.data))
+    }
+
+    /// The outputs exported into a case's folder. They're exported under their type's file
+    /// extension, so anything not of the expected type is missing.
+    fn read_exports(
+        folder: &Path,
+        outputs: &[Output],
+    ) -> Result<HashMap<String, TIEntry>, TestError> {
+        let mut exports = HashMap::new();
+
+        for output in outputs {
+            let extensions = match output {
+                Output::Expected(variable) => vec![variable.value.file_extension()],
+                Output::Generated(_) => EXPORT_EXTENSIONS.to_vec(),
+            };
+
+            let Some(actual_path) = extensions
+                .into_iter()
+                .map(|extension| {
+                    folder.join(format!(
+                        "{}.{}",
+                        translate_variable_name(output.name()),
+                        extension
+                    ))
+                })
+                .find(|path| path.exists())
+            else {
+                continue;
+            };
+
+            exports.insert(output.name().to_owned(), Self::read_entry(&actual_path)?);
+        }
+
+        Ok(exports)
+    }
+}
+
+impl TestBackend for CEmuBackend {
+    /// Runs every case in one emulator session where possible.
+    fn run_cases(
+        &mut self,
+        program: &Tokens,
+        cases: &[TestCase],
+        timeout: Duration,
+    ) -> Result<Vec<CaseOutcome>, TestError> {
+        if cases.is_empty() {
+            return Ok(vec![]);
+        }
+        let case_time = Duration::from_millis(CASE_DELAY_MS.into());
+
+        // a submission which stops would stop the driver along with it
+        let batchable = !stops(program)
+            && cases.iter().all(|case| {
+                case.display.is_none()
+                    && !matches!(case.graph, Some(Graph::Crcs(_)))
+                    && case.error.is_none()
+                    && case
+                        .inputs
+                        .iter()
+                        .all(|input| variable_literal(&input.value).is_some())
+            });
+
+        if batchable {
+            // the driver can't get past a case which doesn't return to it, so the cases after one
+            // are run in a session of their own
+            let mut outcomes = self.run_session(program, cases, case_time, timeout)?;
+            let remaining = &cases[outcomes.len()..];
+            outcomes.extend(self.run_cases(program, remaining, timeout)?);
+
+            return Ok(outcomes);
+        }
+
+        // inputs which the driver can't write have to be transferred, the autotester only reports
+        // how many hashes didn't match, and a driver which is stopped can't run any more cases, so
+        // these are run one case, and one hashed check, at a time
+        let mut outcomes = vec![];
+        for case in cases {
+            let parts = hashed_parts(case)
+                .iter()
+                .map(|part| {
+                    let outcome =
+                        self.run_session(program, std::slice::from_ref(part), case_time, timeout)?;
+                    Ok(outcome.into_iter().next().unwrap())
+                })
+                .collect::<Result<Vec<_>, TestError>>()?;
+
+            outcomes.push(parts.into_iter().reduce(merge_outcomes).unwrap());
+        }
+
+        Ok(outcomes)
+    }
+
+    fn time_runs(
+        &mut self,
+        program: &Tokens,
+        speed: &Speed,
+        _timeout: Duration,
+    ) -> Result<Option<f64>, TestError> {
+        // the runs are given as long as the speed test allows
+        self.time_session(program, speed)
+    }
+
+    fn checks_graphs(&self) -> bool {
+        true
+    }
+}
+
+pub struct TestRunner {
+    cemu: CEmuBackend,
+    interpreter: InterpreterBackend,
+    /// Used for lessons which don't set their own timeout.
+    timeout: Duration,
+    /// Expected values of outputs given only by name, by lesson id and case.
+    generated_outputs: HashMap<(u16, CaseKey), Vec<Variable>>,
+    /// The graph screens drawn by reference programs, by lesson id, reference program and case.
+    reference_graphs: HashMap<(u16, String, CaseKey), Vec<u8>>,
+    /// How many seconds the starting program takes for the lesson's speed test, by lesson id.
+    starting_program_speeds: HashMap<u16, f64>,
+}
+
+impl TestRunner {
+    pub fn new(timeout: Duration) -> Self {
+        TestRunner {
+            cemu: CEmuBackend::default(),
+            interpreter: InterpreterBackend,
+            timeout,
+            generated_outputs: HashMap::new(),
+            reference_graphs: HashMap::new(),
+            starting_program_speeds: HashMap::new(),
+        }
+    }
+
+    /// Whether submissions are tested with CEmu rather than the built-in interpreter.
+    pub fn uses_cemu(&mut self) -> bool {
+        self.cemu.is_available()
+    }
+
+    /// CEmu if it can be run, otherwise the built-in interpreter.
+    fn backend(&mut self) -> &mut dyn TestBackend {
+        if self.uses_cemu() {
+            &mut self.cemu
+        } else {
+            &mut self.interpreter
+        }
+    }
+
+    fn run_cases(
+        &mut self,
+        program: &Tokens,
+        cases: &[TestCase],
+        lesson: &Lesson,
+    ) -> Result<Vec<ProgramTestResult>, TestError> {
+        let timeout = lesson.timeout.map_or(self.timeout, Duration::from_secs);
+
+        let expected_outputs = self.expected_outputs(cases, lesson, timeout)?;
+        let expected_graphs = self.expected_graphs(cases, lesson, timeout)?;
+        let outcomes = self.backend().run_cases(program, cases, timeout)?;
+
+        Ok(cases
+            .iter()
+            .zip(expected_outputs.iter().zip(expected_graphs))
+            .zip(outcomes)
+            .map(|((case, (outputs, graph)), outcome)| match outcome {
+                CaseOutcome::Finished {
+                    exports,
+                    screen,
+                    graph: graph_check,
+                    ending,
+                } => validate_test_state(
+                    case,
+                    outputs,
+                    graph.as_deref(),
+                    &exports,
+                    screen,
+                    graph_check,
+                    ending,
+                ),
+                CaseOutcome::TimedOut(ran) => ProgramTestResult::Timeout(ran),
+            })
+            .collect())
+    }
+
+    /// The graph screen each case's reference program draws, if it has one. Each is only run
+    /// once.
+    fn expected_graphs(
+        &mut self,
+        cases: &[TestCase],
+        lesson: &Lesson,
+        timeout: Duration,
+    ) -> Result<Vec<Option<Vec<u8>>>, TestError> {
+        let key =
+            |case: &TestCase, reference: &str| (lesson.id, reference.to_owned(), CaseKey::of(case));
+
+        for case in cases {
+            let Some(Graph::Like(reference)) = case.graph else {
+                continue;
+            };
+            if self.reference_graphs.contains_key(&key(case, reference)) {
+                continue;
+            }
+
+            let outcome = self
+                .backend()
+                .run_cases(&tokenize(reference), std::slice::from_ref(case), timeout)?
+                .pop();
+
+            let picture = match outcome {
+                Some(CaseOutcome::Finished {
+                    graph: Some(GraphCheck::Picture(Some(picture))),
+                    ..
+                }) => picture,
+                Some(CaseOutcome::Finished { .. }) => {
+                    return Err(TestError::UnknownGraph(format!(
+                        "the graph screen could not be saved when {}",
+                        describe_case(case)
+                    )))
+                }
+                _ => {
+                    return Err(TestError::UnknownGraph(format!(
+                        "it timed out when {}",
+                        describe_case(case)
+                    )))
+                }
+            };
+
+            self.reference_graphs.insert(key(case, reference), picture);
+        }
+
+        Ok(cases
+            .iter()
+            .map(|case| match case.graph {
+                Some(Graph::Like(reference)) => {
+                    self.reference_graphs.get(&key(case, reference)).cloned()
+                }
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// The expected value of each case's outputs. Outputs given only by name are found by
+    /// running the lesson's starting program on the case's inputs, which is only done once.
+    fn expected_outputs(
+        &mut self,
+        cases: &[TestCase],
+        lesson: &Lesson,
+        timeout: Duration,
+    ) -> Result<Vec<Vec<Variable>>, TestError> {
+        let key = |case: &TestCase| (lesson.id, CaseKey::of(case));
+
+        // cases with the same inputs may generate different outputs
+        let missing = cases
+            .iter()
+            .filter(|case| {
+                let known = self.generated_outputs.get(&key(case));
+                case.generated_outputs().any(|name| {
+                    !known.is_some_and(|known| known.iter().any(|output| output.name == *name))
+                })
+            })
+            .copied()
+            .collect::<Vec<_>>();
+
+        if !missing.is_empty() {
+            let starting_program = tokenize(&lesson.starting_program);
+            let outcomes = self
+                .backend()
+                .run_cases(&starting_program, &missing, timeout)?;
+
+            for (case, outcome) in missing.iter().zip(outcomes) {
+                let CaseOutcome::Finished { exports, .. } = outcome else {
+                    return Err(TestError::UnknownOutput(format!(
+                        "it timed out when {}",
+                        describe_case(case)
+                    )));
+                };
+
+                let generated = case
+                    .generated_outputs()
+                    .map(|name| {
+                        let entry = exports.get(name).ok_or_else(|| {
+                            TestError::UnknownOutput(format!(
+                                "it did not leave {} behind when {}",
+                                name,
+                                describe_case(case)
+                            ))
+                        })?;
+
+                        Ok(Variable {
+                            name: name.clone(),
+                            value: VariableData::try_from(entry)?,
+                            comparison: Comparison::default(),
+                        })
+                    })
+                    .collect::<Result<Vec<_>, TestError>>()?;
+
+                let known = self.generated_outputs.entry(key(case)).or_default();
+                known.retain(|output| !generated.iter().any(|new| new.name == output.name));
+                known.extend(generated);
+            }
+        }
+
+        Ok(cases
+            .iter()
+            .map(|case| {
+                let known = self.generated_outputs.get(&key(case));
+
+                case.outputs
+                    .iter()
+                    .map(|output| match output {
+                        Output::Expected(variable) => variable.clone(),
+                        Output::Generated(name) => known
+                            .into_iter()
+                            .flatten()
+                            .find(|output| output.name == *name)
+                            .unwrap()
+                            .clone(),
+                    })
+                    .collect()
+            })
+            .collect())
+    }
+
+    /// Drops the expected outputs found for `cases`.
+    fn forget_outputs(&mut self, cases: &[TestCase], lesson: &Lesson) {
+        for case in cases {
+            self.generated_outputs
+                .remove(&(lesson.id, CaseKey::of(case)));
+        }
+    }
+
+    /// Times the program with the lesson's speed test, if it has one.
+    pub fn run_speed_test(
+        &mut self,
+        program: &Tokens,
+        lesson: &Lesson,
+    ) -> Result<Option<SpeedReport>, TestError> {
+        let Some(speed) = &lesson.speed else {
+            return Ok(None);
+        };
+        let timeout = lesson.timeout.map_or(self.timeout, Duration::from_secs);
+
+        // a program which stops would end the timing driver along with it
+        if stops(program) {
+            return Ok(Some(SpeedReport {
+                result: ProgramTestResult::Fail(
+                    "The program can't be timed, because it uses Stop.".to_owned(),
+                ),
+                runs: speed.runs,
+                seconds: None,
+                starting_program_seconds: None,
+            }));
+        }
+
+        let starting_program_seconds = if speed.no_slower {
+            Some(self.starting_program_speed(lesson, speed, timeout)?)
+        } else {
+            None
+        };
+        let seconds = self
+            .backend()
+            .time_runs(program, speed, timeout)?
+            .filter(|&seconds| seconds <= speed.seconds.into());
+
+        let result = match (seconds, starting_program_seconds) {
+            (None, _) => ProgramTestResult::Fail(format!(
+                "{} runs of the program took longer than {}.",
+                speed.runs,
+                seconds_text(speed.seconds.into())
+            )),
+            (Some(seconds), Some(starting_seconds)) if seconds > starting_seconds => {
+                ProgramTestResult::Fail(format!(
+                    "{} runs of the program took {}, but the starting program's took {}.",
+                    speed.runs,
+                    seconds_text(seconds),
+                    seconds_text(starting_seconds)
+                ))
+            }
+            _ => ProgramTestResult::Pass,
+        };
+
+        Ok(Some(SpeedReport {
+            result,
+            runs: speed.runs,
+            seconds,
+            starting_program_seconds,
+        }))
+    }
+
+    /// How many seconds the starting program takes for the lesson's speed test. It's only timed
+    /// once.
+    fn starting_program_speed(
+        &mut self,
+        lesson: &Lesson,
+        speed: &Speed,
+        timeout: Duration,
+    ) -> Result<f64, TestError> {
+        if let Some(&seconds) = self.starting_program_speeds.get(&lesson.id) {
+            return Ok(seconds);
+        }
+
+        let seconds = self
+            .backend()
+            .time_runs(&tokenize(&lesson.starting_program), speed, timeout)?
+            .filter(|&seconds| seconds <= speed.seconds.into())
+            .ok_or(TestError::UnknownSpeed(speed.seconds))?;
+
+        self.starting_program_speeds.insert(lesson.id, seconds);
+        Ok(seconds)
+    }
+
+    /// Test program.
+    ///
+    /// If any of the root tests pass, the program passes.
+    /// All of the tests in a test group must pass for the whole group to pass.
+    ///
+    /// Along with the result, there's a report on each of the lesson's tests.
+    pub fn run_tests(
+        &mut self,
+        program: &Tokens,
+        lesson: &Lesson,
+    ) -> Result<(ProgramTestResult, Vec<TestReport>), TestError> {
+        let mut last_result = ProgramTestResult::Fail("Tests failed.".to_owned());
+        let mut reports = vec![];
+
+        for test in &lesson.tests {
+            // an alternative which can't be checked can't pass, but another one still might
+            if last_result.passed() || (checks_graph(test) && !self.backend().checks_graphs()) {
+                reports.push(TestReport::skipped(test));
+                continue;
+            }
+
+            let report = self.run_test(program, test, lesson)?;
+            last_result = report.result.clone().unwrap();
+            reports.push(report);
+        }
+
+        Ok((last_result, reports))
+    }
+
+    /// Runs every CEmu test in the lesson, including those in groups, in a single session.
+    ///
+    /// Unlike [TestRunner::run_tests], all of them must pass: a lesson's starting program should
+    /// do everything the tests expect, even though only some of them are needed to pass.
+    pub fn run_behavior_tests(
+        &mut self,
+        program: &Tokens,
+        lesson: &Lesson,
+    ) -> Result<ProgramTestResult, TestError> {
+        fn cases(test: &Test) -> Vec<TestCase<'_>> {
+            match test {
+                Test::Group(group) => group.iter().flat_map(cases).collect(),
+                // random tests are only compared against the starting program itself
+                test => TestCase::of(test),
+            }
+        }
+
+        // the graph screen can't be checked everywhere, but the rest of the lesson still can
+        let checks_graphs = self.backend().checks_graphs();
+        let cases = lesson
+            .tests
+            .iter()
+            .flat_map(cases)
+            .filter(|case| checks_graphs || case.graph.is_none())
+            .collect::<Vec<_>>();
+        if cases.is_empty() {
+            return Ok(ProgramTestResult::Pass);
+        }
+
+        Ok(first_failure(self.run_cases(program, &cases, lesson)?))
+    }
+
+    fn run_test(
+        &mut self,
+        program: &Tokens,
+        test: &Test,
+        lesson: &Lesson,
+    ) -> Result<TestReport, TestError> {
+        let start = Instant::now();
+
+        let (result, test) = match test {
+            Test::CEmu { .. } => (
+                first_failure(self.run_cases(program, &TestCase::of(test), lesson)?),
+                TestKind::of(test),
+            ),
+            Test::FulltextMatch { regex } => {
+                let regex_result = Regex::new(&("^".to_owned() + regex + "$"))
                     .unwrap()
                     .is_match(&program.to_string(tokenizer()))
                     .map_err(|err| TestError::Regex(Box::new(err)))?;
 
-                if regex_result {
-                    Ok(ProgramTestResult::Pass)
+                let result = if regex_result {
+                    ProgramTestResult::Pass
                 } else {
-                    Ok(ProgramTestResult::Fail("Tests failed.".to_owned()))
-                }
+                    ProgramTestResult::Fail("Tests failed.".to_owned())
+                };
+
+                (result, TestKind::of(test))
+            }
+            Test::TokenCount {
+                token,
+                at_least,
+                at_most,
+            } => {
+                let result = match token_count_failure(program, token, *at_least, *at_most) {
+                    Some(reason) => ProgramTestResult::Fail(reason),
+                    None => ProgramTestResult::Pass,
+                };
+
+                (result, TestKind::of(test))
+            }
+            Test::Random {
+                random,
+                output,
+                cases,
+                seed,
+            } => {
+                let seed = seed.unwrap_or_else(|| fastrand::u64(..));
+                let mut rng = fastrand::Rng::with_seed(seed);
+
+                let inputs = (0..*cases)
+                    .map(|_| {
+                        random
+                            .iter()
+                            .map(|input| Variable {
+                                name: input.name.clone(),
+                                value: input.domain.sample(&mut rng),
+                                comparison: Comparison::default(),
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                    .collect::<Vec<_>>();
+                let outputs = output
+                    .iter()
+                    .cloned()
+                    .map(Output::Generated)
+                    .collect::<Vec<_>>();
+
+                let cases = inputs
+                    .iter()
+                    .map(|inputs| TestCase {
+                        inputs,
+                        outputs: &outputs,
+                        seed: None,
+                        display: None,
+                        graph: None,
+                        error: None,
+                        keys: &[],
+                    })
+                    .collect::<Vec<_>>();
+
+                // these inputs are unlikely to come up again
+                let results = self.run_cases(program, &cases, lesson);
+                self.forget_outputs(&cases, lesson);
+
+                let result = match first_failure(results?) {
+                    ProgramTestResult::Fail(reason) => ProgramTestResult::Fail(format!(
+                        "{}\nThe inputs were chosen randomly, with seed {}.",
+                        reason, seed
+                    )),
+                    result => result,
+                };
+
+                (
+                    result,
+                    TestKind::Random {
+                        seed: Some(seed),
+                        cases: cases.len(),
+                    },
+                )
             }
             Test::Group(group) => {
+                let cases = group.iter().flat_map(TestCase::of).collect::<Vec<_>>();
+
+                // all of the group's CEmu tests share a session, which is only started once needed
+                let mut case_results = None;
+                let mut result = ProgramTestResult::Pass;
+                let mut reports = vec![];
+
                 for test in group {
-                    if let ProgramTestResult::Fail(reason) = self.run_test(program.clone(), test)? {
-                        return Ok(ProgramTestResult::Fail(reason));
+                    let report = match test {
+                        Test::CEmu { .. } if case_results.is_none() && !result.passed() => {
+                            TestReport::skipped(test)
+                        }
+                        Test::CEmu { .. } => {
+                            if case_results.is_none() {
+                                case_results =
+                                    Some(self.run_cases(program, &cases, lesson)?.into_iter());
+                            }
+
+                            // a test with several seeds fails with its first failing case
+                            let test_results = case_results
+                                .as_mut()
+                                .unwrap()
+                                .take(TestCase::of(test).len())
+                                .collect();
+
+                            TestReport {
+                                result: Some(first_failure(test_results)),
+                                seconds: None,
+                                test: TestKind::of(test),
+                            }
+                        }
+                        _ if !result.passed() => TestReport::skipped(test),
+                        _ => self.run_test(program, test, lesson)?,
+                    };
+
+                    // the group fails with its first failing test
+                    if let Some(test_result) = &report.result {
+                        if result.passed() && !test_result.passed() {
+                            result = test_result.clone();
+                        }
                     }
+
+                    reports.push(report);
                 }
 
-                Ok(ProgramTestResult::Pass)
+                (result, TestKind::Group { tests: reports })
             }
+        };
+
+        Ok(TestReport {
+            result: Some(result),
+            seconds: Some(start.elapsed().as_secs_f64()),
+            test,
+        })
+    }
+}
+
+/// Whether any of the test's cases expect a graph screen.
+fn checks_graph(test: &Test) -> bool {
+    match test {
+        Test::CEmu { graph, .. } => graph.is_some(),
+        Test::Group(group) => group.iter().any(checks_graph),
+        _ => false,
+    }
+}
+
+/// Why the program uses a token too few or too many times, if it does.
+fn token_count_failure(
+    program: &Tokens,
+    token: &str,
+    at_least: Option<usize>,
+    at_most: Option<usize>,
+) -> Option<String> {
+    // lessons' tokens are checked to be exactly one token when they're read
+    let expected = tokenize(token).next().unwrap();
+    let count = program.clone().filter(|&token| token == expected).count();
+    // names like `If ` end with a space, which is hard to see in a message
+    let token = token.trim_end();
+
+    let times = |count| match count {
+        1 => "once".to_owned(),
+        count => format!("{} times", count),
+    };
+
+    match (at_least, at_most) {
+        (Some(at_least), _) if count == 0 && at_least > 0 => {
+            Some(format!("The program must use {}.", token))
         }
+        (Some(at_least), _) if count < at_least => Some(format!(
+            "The program uses {} {}, but must use it at least {}.",
+            token,
+            times(count),
+            times(at_least)
+        )),
+        (_, Some(0)) if count > 0 => Some(format!("The program must not use {}.", token)),
+        (_, Some(at_most)) if count > at_most => Some(format!(
+            "The program uses {} {}, but can use it at most {}.",
+            token,
+            times(count),
+            times(at_most)
+        )),
+        _ => None,
+    }
+}
+
+/// The first of the results which didn't pass, if any.
+fn first_failure(results: Vec<ProgramTestResult>) -> ProgramTestResult {
+    results
+        .into_iter()
+        .find(|result| !result.passed())
+        .unwrap_or(ProgramTestResult::Pass)
+}
+
+/// Compares what a case left behind against its expected outputs and screens.
+fn validate_test_state(
+    case: &TestCase,
+    outputs: &[Variable],
+    expected_graph: Option<&[u8]>,
+    exports: &HashMap<String, TIEntry>,
+    screen: Option<ScreenCheck>,
+    graph: Option<GraphCheck>,
+    ending: Ending,
+) -> ProgramTestResult {
+    if let Some(reason) = error_failure(case, &ending) {
+        return ProgramTestResult::Fail(reason);
+    }
+
+    // an error is the likeliest reason for outputs to be missing or wrong
+    let outputs_result = validate_outputs(case, outputs, exports);
+    if let (ProgramTestResult::Fail(_), Ending::Stopped(kind)) = (&outputs_result, &ending) {
+        return ProgramTestResult::Fail(format!(
+            "The program stopped with {} when {}.",
+            kind,
+            describe_case(case)
+        ));
+    }
+    if !outputs_result.passed() {
+        return outputs_result;
+    }
+
+    if let Some(ScreenCheck::Differs(actual)) = screen {
+        let expected = case
+            .display
+            .and_then(|display| HomeScreen::showing(display).ok())
+            .unwrap_or_default();
+
+        let mut reason = format!(
+            "Incorrect home screen when {}.\nExpected:\n{}",
+            describe_case(case),
+            screen_text(&expected.lines())
+        );
+        if let Some(actual) = actual {
+            reason += &format!("\nActual:\n{}", screen_text(&actual));
+        }
+
+        return ProgramTestResult::Fail(reason);
+    }
+
+    let graph_matches = match graph {
+        Some(GraphCheck::Crc(matches)) => matches,
+        Some(GraphCheck::Picture(picture)) => picture.as_deref() == expected_graph,
+        None => true,
+    };
+    if !graph_matches {
+        return ProgramTestResult::Fail(format!(
+            "Incorrect graph screen when {}.",
+            describe_case(case)
+        ));
+    }
+
+    ProgramTestResult::Pass
+}
+
+/// Compares the outputs a case left behind against their expected values.
+fn validate_outputs(
+    case: &TestCase,
+    outputs: &[Variable],
+    exports: &HashMap<String, TIEntry>,
+) -> ProgramTestResult {
+    for output in outputs {
+        let Some(actual) = exports.get(&output.name) else {
+            if output.name == "Ans" {
+                return ProgramTestResult::Fail(format!("Cannot find Ans when {}; perhaps Ans is the wrong type at the end of your program.", describe_case(case)));
+            }
+
+            return ProgramTestResult::Fail(format!(
+                "Cannot find variable {} when {}.",
+                output.name,
+                describe_case(case)
+            ));
+        };
+
+        let expected: TIEntry = output.clone().into();
+
+        if !actual.matches(&expected, output.comparison) {
+            let actual_value = VariableData::try_from(actual)
+                .map_or_else(|err| err.to_string(), |value| value.to_string());
+
+            return ProgramTestResult::Fail(format!(
+                "Incorrect value for variable {} when {}.\nExpected: {}\nActual:   {}",
+                output.name,
+                describe_case(case),
+                output.value,
+                actual_value
+            ));
+        }
+    }
+
+    ProgramTestResult::Pass
+}
+
+/// Why the program didn't end as the case expected, if it didn't.
+fn error_failure(case: &TestCase, ending: &Ending) -> Option<String> {
+    let expected = case.error?;
+    let when = describe_case(case);
+
+    match (expected, ending) {
+        (_, Ending::Unknown(Some(true) | None)) => None,
+        (ExpectedError::Nothing, Ending::Completed) => None,
+        (ExpectedError::Nothing, Ending::Stopped(actual)) => Some(format!(
+            "The program stopped with {} when {}.",
+            actual, when
+        )),
+        (ExpectedError::Nothing, Ending::Unknown(_)) => {
+            Some(format!("The program stopped with an error when {}.", when))
+        }
+        (ExpectedError::Error(kind), Ending::Stopped(actual)) if kind == *actual => None,
+        (ExpectedError::Error(kind), Ending::Stopped(actual)) => Some(format!(
+            "Expected the program to stop with {} when {}, but it stopped with {}.",
+            kind, when, actual
+        )),
+        (ExpectedError::Error(kind), Ending::Completed) => Some(format!(
+            "Expected the program to stop with {} when {}, but it finished without an error.",
+            kind, when
+        )),
+        (ExpectedError::Error(kind), Ending::Unknown(_)) => Some(format!(
+            "Expected the program to stop with {} when {}, but it did not.",
+            kind, when
+        )),
+    }
+}
+
+/// The lines of a home screen, indented so that they stand out from the message around them.
+fn screen_text(lines: &[String]) -> String {
+    if lines.is_empty() {
+        return "  (blank)".to_owned();
+    }
+
+    lines
+        .iter()
+        .map(|line| format!("  {}", line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The autotester only counts failed hashes, so a case's home screen and error have to be
+    /// checked in sessions of their own to know which one failed.
+    #[test]
+    fn checks_display_and_error_separately() {
+        let test: Test = serde_json::from_str(
+            r#"{"input": [{"name": "A", "value": 5}], "display": [5], "error": "none"}"#,
+        )
+        .unwrap();
+        let case = TestCase::of(&test)[0];
+
+        let parts = hashed_parts(&case);
+        assert_eq!(parts.len(), 2);
+        assert!(parts[0].display.is_some() && parts[0].error.is_none());
+        assert!(parts[1].display.is_none() && parts[1].error.is_some());
+
+        // the home screen was right, but the program stopped with an error
+        let outcome = merge_outcomes(
+            CaseOutcome::Finished {
+                exports: HashMap::new(),
+                screen: Some(ScreenCheck::Matches),
+                graph: None,
+                ending: Ending::Unknown(None),
+            },
+            CaseOutcome::Finished {
+                exports: HashMap::new(),
+                screen: None,
+                graph: None,
+                ending: Ending::Unknown(Some(false)),
+            },
+        );
+        let CaseOutcome::Finished {
+            exports,
+            screen,
+            graph,
+            ending,
+        } = outcome
+        else {
+            panic!("the case should have finished");
+        };
+        assert!(matches!(screen, Some(ScreenCheck::Matches)));
+
+        let result = validate_test_state(&case, &[], None, &exports, screen, graph, ending);
+        assert_eq!(
+            result.to_string(),
+            "The program stopped with an error when A=5."
+        );
+    }
+    /// A session is given as long as its cases' delays add up to, however many there are, rather
+    /// than the timeout of any one case.
+    #[test]
+    fn session_fits_its_cases() {
+        let seeds = (1..=20).map(|seed| seed.to_string()).collect::<Vec<_>>();
+        let test: Test = serde_json::from_str(&format!(
+            r#"{{"input": [], "output": ["A"], "seeds": [{}]}}"#,
+            seeds.join(", ")
+        ))
+        .unwrap();
+        let cases = TestCase::of(&test);
+        assert_eq!(cases.len(), 20);
+
+        let case_time = Duration::from_millis(CASE_DELAY_MS.into());
+        let config = AutotesterConfig::for_cases("rom".to_owned(), &cases, case_time).unwrap();
+
+        let delays = Duration::from_millis(LAUNCH_DELAY_MS.into()) + case_time * 20;
+        assert!(config.time_limit() > delays);
+    }
+
+    /// The driver's loops tell apart runs which take the same whole number of seconds.
+    #[test]
+    fn times_parts_of_a_second() {
+        // 1 second, then 30 of the 100 loops which fit in a second
+        assert_eq!(timed_seconds(&[0.0, 1.0, 30.0, 100.0]), Some(1.7));
+        assert_eq!(timed_seconds(&[0.0, 1.0, 90.0, 100.0]), Some(1.1));
+        // the runs weren't done
+        assert_eq!(timed_seconds(&[0.0]), None);
+
+        assert_eq!(seconds_text(1.004), "1 second");
+        assert_eq!(seconds_text(1.7), "1.7 seconds");
     }
 }
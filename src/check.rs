@@ -0,0 +1,484 @@
+//! `basiclings check-lessons`, which looks for mistakes in lessons before students run into them.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use fancy_regex::Regex;
+
+use crate::{
+    cemu::{TestError, TestRunner, CASE_DELAY_MS},
+    cli::EXIT_FAILED,
+    config::{Config, DEFAULT_TIMEOUT_SECONDS},
+    lesson::{Domain, Graph, Lesson, Output, Test, Variable, VariableData, CUSTOM_LIST_PREFIX},
+    parser::lesson_files,
+    screen::HomeScreen,
+    tools::{byte_count, process_submission, strip_comments, tokenize, tokenizer},
+};
+
+/// Something wrong with a lesson file.
+pub struct Problem {
+    pub path: String,
+    pub message: String,
+}
+
+pub fn run_check_command(lessons_dir: Option<PathBuf>, verify: bool) -> ExitCode {
+    let config = Config::load();
+    let lessons_dir = lessons_dir.or(config.lessons_dir.clone());
+
+    let mut test_runner = verify.then(|| TestRunner::new(config.timeout()));
+    let (lesson_count, problems) = check_lessons(lessons_dir.as_deref(), test_runner.as_mut());
+
+    for problem in &problems {
+        println!("{}: {}", problem.path, problem.message);
+    }
+
+    match problems.len() {
+        0 => {
+            println!("Checked {} lessons: no problems found.", lesson_count);
+            ExitCode::SUCCESS
+        }
+        count => {
+            println!(
+                "Checked {} lessons: {} problem{} found.",
+                lesson_count,
+                count,
+                if count > 1 { "s" } else { "" }
+            );
+            ExitCode::from(EXIT_FAILED)
+        }
+    }
+}
+
+/// Checks the embedded lessons and any in `lessons_dir`, returning how many lessons students
+/// would see and the problems found. Lessons are also tested if there's a `test_runner`.
+pub fn check_lessons(
+    lessons_dir: Option<&Path>,
+    mut test_runner: Option<&mut TestRunner>,
+) -> (usize, Vec<Problem>) {
+    let mut problems = Vec::new();
+
+    // lessons on disk may replace embedded lessons, but not other lessons from the same place
+    let mut lessons: BTreeMap<u16, (String, Lesson)> = BTreeMap::new();
+    let mut first_paths: BTreeMap<(bool, u16), String> = BTreeMap::new();
+
+    for file in lesson_files(lessons_dir) {
+        let lesson = match file.lesson {
+            Ok((_, lesson)) => lesson,
+            Err(err) => {
+                problems.push(Problem {
+                    path: file.path,
+                    message: err.to_string(),
+                });
+                continue;
+            }
+        };
+
+        if let Some(first_path) = first_paths.get(&(file.embedded, lesson.id)) {
+            problems.push(Problem {
+                message: format!("Lesson id {} is already used by {}.", lesson.id, first_path),
+                path: file.path,
+            });
+            continue;
+        }
+        first_paths.insert((file.embedded, lesson.id), file.path.clone());
+
+        let mut messages = check_lesson(&lesson);
+
+        // a lesson which can't be tokenized would only crash the tests
+        if let (true, Some(test_runner)) = (messages.is_empty(), test_runner.as_deref_mut()) {
+            messages.extend(verify_lesson(&lesson, test_runner).unwrap_or_else(|err| {
+                vec![format!(
+                    "Could not be tested: {}",
+                    err.to_string().trim_end()
+                )]
+            }));
+        }
+
+        problems.extend(messages.into_iter().map(|message| Problem {
+            path: file.path.clone(),
+            message,
+        }));
+
+        lessons.insert(lesson.id, (file.path, lesson));
+    }
+
+    for (path, lesson) in lessons.values() {
+        for requirement in &lesson.requirements {
+            if !lessons.contains_key(requirement) {
+                problems.push(Problem {
+                    path: path.clone(),
+                    message: format!("Requires lesson {}, which does not exist.", requirement),
+                });
+            }
+        }
+    }
+
+    for cycle in requirement_cycles(&lessons) {
+        let message = match cycle.as_slice() {
+            [id] => format!(
+                "Lesson {} requires itself, so it can never be unlocked.",
+                id
+            ),
+            _ => format!(
+                "Lessons {} -> {} require each other, so they can never be unlocked.",
+                cycle
+                    .iter()
+                    .map(|id| id.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" -> "),
+                cycle[0]
+            ),
+        };
+
+        problems.push(Problem {
+            path: lessons[&cycle[0]].0.clone(),
+            message,
+        });
+    }
+
+    (lessons.len(), problems)
+}
+
+/// Problems which can be found by looking at one lesson on its own.
+fn check_lesson(lesson: &Lesson) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    match tokenizer().tokenize(&lesson.starting_program) {
+        Ok((tokens, _)) => {
+            let size = byte_count(&tokens.collect::<Vec<_>>());
+
+            if lesson.required_savings >= size {
+                problems.push(format!(
+                    "required_savings ({}) must be less than the starting program's size ({} bytes).",
+                    lesson.required_savings, size
+                ));
+            }
+        }
+        Err(_) => problems.push("The starting program does not tokenize.".to_owned()),
+    }
+
+    for (index, solution) in lesson.reference_solutions.iter().enumerate() {
+        let is_tokenizable = tokenizer().tokenize(&strip_comments(solution)).is_ok();
+
+        if !is_tokenizable {
+            problems.push(format!(
+                "Reference solution {} does not tokenize.",
+                index + 1
+            ));
+        }
+    }
+
+    let timeout = lesson.timeout.unwrap_or(DEFAULT_TIMEOUT_SECONDS);
+    for test in &lesson.tests {
+        check_test(test, timeout, &mut problems);
+    }
+
+    if let Some(speed) = &lesson.speed {
+        for variable in &speed.input {
+            check_variable(variable, &mut problems);
+        }
+
+        if speed.runs == 0 || speed.seconds == 0 {
+            problems.push("A speed test needs at least one run and one second.".to_owned());
+        }
+    }
+
+    problems
+}
+
+/// Tests that the starting program does what the tests expect (it's only too large), and
+/// that each reference solution is small enough and passes, including any speed test.
+fn verify_lesson(lesson: &Lesson, test_runner: &mut TestRunner) -> Result<Vec<String>, TestError> {
+    let mut problems = Vec::new();
+
+    let result = test_runner.run_behavior_tests(&tokenize(&lesson.starting_program), lesson)?;
+    if !result.passed() {
+        problems.push(format!("The starting program fails its tests: {}", result));
+    }
+
+    let speed = test_runner.run_speed_test(&tokenize(&lesson.starting_program), lesson)?;
+    if let Some(speed) = speed.filter(|speed| !speed.result.passed()) {
+        problems.push(format!(
+            "The starting program fails its speed test: {}",
+            speed.result
+        ));
+    }
+
+    let byte_threshold = lesson.byte_threshold();
+
+    for (index, solution) in lesson.reference_solutions.iter().enumerate() {
+        let tokens = process_submission(solution.clone()).map_err(TestError::Untokenizable)?;
+        let size = byte_count(&tokens.clone().collect::<Vec<_>>());
+
+        if size > byte_threshold {
+            problems.push(format!(
+                "Reference solution {} is {} bytes, but the target is {} bytes.",
+                index + 1,
+                size,
+                byte_threshold
+            ));
+            continue;
+        }
+
+        let (result, _) = test_runner.run_tests(&tokens, lesson)?;
+        if !result.passed() {
+            problems.push(format!(
+                "Reference solution {} fails its tests: {}",
+                index + 1,
+                result
+            ));
+            continue;
+        }
+
+        let speed = test_runner.run_speed_test(&tokens, lesson)?;
+        if let Some(speed) = speed.filter(|speed| !speed.result.passed()) {
+            problems.push(format!(
+                "Reference solution {} fails its speed test: {}",
+                index + 1,
+                speed.result
+            ));
+        }
+    }
+
+    Ok(problems)
+}
+
+/// `timeout` is the lesson's, in seconds.
+fn check_test(test: &Test, timeout: u64, problems: &mut Vec<String>) {
+    match test {
+        Test::CEmu {
+            input,
+            output,
+            display,
+            graph,
+            error,
+            ..
+        } => {
+            for variable in input {
+                check_variable(variable, problems);
+            }
+
+            if let Some(Err(err)) = display.as_deref().map(HomeScreen::showing) {
+                problems.push(format!("The expected home screen can't be shown: {}.", err));
+            }
+
+            match graph {
+                Some(Graph::Crcs(crcs)) => {
+                    if crcs.is_empty() {
+                        problems.push("A graph screen test needs at least one CRC.".to_owned());
+                    }
+
+                    for crc in crcs {
+                        if u32::from_str_radix(crc, 16).is_err() {
+                            problems.push(format!("The CRC {} is not a 32-bit hex number.", crc));
+                        }
+                    }
+                }
+                Some(Graph::Like(reference)) if tokenizer().tokenize(reference).is_err() => {
+                    problems.push(format!(
+                        "The graph's reference program does not tokenize: {}",
+                        reference
+                    ));
+                }
+                _ => {}
+            }
+
+            // the autotester only reports how many hashes didn't match
+            let hashes = display.is_some() as usize
+                + matches!(graph, Some(Graph::Crcs(_))) as usize
+                + error.is_some() as usize;
+            if hashes > 1 {
+                problems.push(
+                    "A test can only check one of the home screen, graph screen CRCs and error."
+                        .to_owned(),
+                );
+            }
+
+            for output in output {
+                match output {
+                    Output::Expected(variable) => check_variable(variable, problems),
+                    Output::Generated(name) => check_variable_name(name, problems),
+                }
+            }
+        }
+        // compiled the same way as when testing
+        Test::FulltextMatch { regex } => {
+            if let Err(err) = Regex::new(&("^".to_owned() + regex + "$")) {
+                problems.push(format!("The regex {} does not compile: {}", regex, err));
+            }
+        }
+        Test::TokenCount {
+            token,
+            at_least,
+            at_most,
+        } => match (at_least, at_most) {
+            (None, None) => problems.push(format!(
+                "The token test for {} needs at_least or at_most.",
+                token.trim_end()
+            )),
+            (Some(at_least), Some(at_most)) if at_least > at_most => problems.push(format!(
+                "The token test for {} can't pass: at_least is more than at_most.",
+                token.trim_end()
+            )),
+            _ => {}
+        },
+        Test::Random {
+            random,
+            output,
+            cases,
+            ..
+        } => {
+            for input in random {
+                check_variable_name(&input.name, problems);
+                problems.extend(input.domain.problems());
+
+                if let Domain::OneOf(values) = &input.domain {
+                    for value in values {
+                        check_value(&input.name, value, problems);
+                    }
+                }
+            }
+
+            for name in output {
+                check_variable_name(name, problems);
+            }
+
+            // the cases are run in turn, each given at least this long
+            if *cases == 0 {
+                problems.push("A random test needs at least one case.".to_owned());
+            } else if *cases as u64 * u64::from(CASE_DELAY_MS) > timeout * 1000 {
+                problems.push(format!(
+                    "A random test's {} cases take longer to run than the {} second timeout.",
+                    cases, timeout
+                ));
+            }
+        }
+        Test::Group(tests) => tests
+            .iter()
+            .for_each(|test| check_test(test, timeout, problems)),
+    }
+}
+
+fn check_variable(variable: &Variable, problems: &mut Vec<String>) {
+    check_variable_name(&variable.name, problems);
+    check_value(&variable.name, &variable.value, problems);
+}
+
+fn check_value(name: &str, value: &VariableData, problems: &mut Vec<String>) {
+    let token_text = match value {
+        VariableData::String(text) => text,
+        VariableData::Equation { equation } => equation,
+        _ => return,
+    };
+
+    if tokenizer().tokenize(token_text).is_err() {
+        problems.push(format!(
+            "The value of {} does not tokenize: {}",
+            name, value
+        ));
+    }
+}
+
+fn check_variable_name(name: &str, problems: &mut Vec<String>) {
+    // custom list names are checked when the lesson is parsed
+    if name.starts_with(CUSTOM_LIST_PREFIX) {
+        return;
+    }
+
+    let is_one_token = tokenizer()
+        .tokenize(name)
+        .is_ok_and(|(tokens, _)| tokens.count() == 1);
+
+    if !is_one_token {
+        problems.push(format!("The variable name {} is not a single token.", name));
+    }
+}
+
+/// Each loop of lessons which (indirectly) require themselves.
+fn requirement_cycles(lessons: &BTreeMap<u16, (String, Lesson)>) -> Vec<Vec<u16>> {
+    fn visit(
+        id: u16,
+        lessons: &BTreeMap<u16, (String, Lesson)>,
+        path: &mut Vec<u16>,
+        finished: &mut BTreeSet<u16>,
+        cycles: &mut Vec<Vec<u16>>,
+    ) {
+        if finished.contains(&id) {
+            return;
+        }
+
+        if let Some(start) = path.iter().position(|&other| other == id) {
+            cycles.push(path[start..].to_vec());
+            return;
+        }
+
+        let Some((_, lesson)) = lessons.get(&id) else {
+            return;
+        };
+
+        path.push(id);
+        for &requirement in &lesson.requirements {
+            visit(requirement, lessons, path, finished, cycles);
+        }
+        path.pop();
+
+        finished.insert(id);
+    }
+
+    let mut finished = BTreeSet::new();
+    let mut cycles = Vec::new();
+
+    for &id in lessons.keys() {
+        visit(id, lessons, &mut Vec::new(), &mut finished, &mut cycles);
+    }
+
+    cycles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lesson(id: u16, requirements: &[u16]) -> (u16, (String, Lesson)) {
+        let lesson = serde_json::from_value(serde_json::json!({
+            "id": id,
+            "name": "",
+            "requirements": requirements,
+            "starting_program": "1",
+            "required_savings": 0,
+            "brief_description": null,
+            "tests": [],
+            "timeout": null,
+        }))
+        .unwrap();
+
+        (id, (format!("{}.md", id), lesson))
+    }
+
+    #[test]
+    fn embedded_lessons_are_valid() {
+        let (_, problems) = check_lessons(None, None);
+
+        let messages: Vec<_> = problems
+            .iter()
+            .map(|problem| format!("{}: {}", problem.path, problem.message))
+            .collect();
+        assert!(messages.is_empty(), "{:#?}", messages);
+    }
+
+    #[test]
+    fn finds_requirement_cycles() {
+        let lessons = BTreeMap::from([
+            lesson(0, &[]),
+            lesson(1, &[0, 3]),
+            lesson(2, &[1]),
+            lesson(3, &[2]),
+            lesson(4, &[4]),
+        ]);
+
+        assert_eq!(requirement_cycles(&lessons), vec![vec![1, 3, 2], vec![4]]);
+    }
+}
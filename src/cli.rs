@@ -1,6 +1,11 @@
 use std::{
     collections::{BTreeMap, BTreeSet},
     fs, io,
+    path::PathBuf,
+    process::ExitCode,
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
 };
 
 use edit::edit;
@@ -13,7 +18,9 @@ use markdown::mdast::{Code, Node};
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    cemu::{ProgramTestResult, TestRunner},
+    args::{OutputFormat, TestOptions},
+    cemu::{ProgramTestResult, SpeedReport, TestError, TestReport, TestRunner},
+    config::Config,
     lesson::Lesson,
     parser::parse_lessons,
     tools::{byte_count, process_submission},
@@ -21,9 +28,17 @@ use crate::{
 
 const SAVE_PATH: &str = "basiclings_save.json";
 const RECOVERY_PATH: &str = "basiclings_recovery.json";
+/// Where `watch` keeps the files being worked on.
+const WORK_DIR: &str = "basiclings_work";
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
 
-const COMMANDS: [&str; 7] = [
-    "help", "select", "next", "retry", "quit", "progress", "review",
+/// Exit status of `basiclings test` when the submission is too large or fails its tests.
+pub const EXIT_FAILED: u8 = 1;
+/// Exit status when the submission couldn't be tested at all.
+pub const EXIT_ERROR: u8 = 2;
+
+const COMMANDS: [&str; 8] = [
+    "help", "select", "next", "retry", "quit", "progress", "review", "watch",
 ];
 
 pub struct UserInterface {
@@ -32,23 +47,34 @@ pub struct UserInterface {
     test_runner: TestRunner,
 
     last_attempt: Option<u16>,
+    /// Whether the user has been told that attempts are tested without CEmu.
+    interpreter_noted: bool,
 }
 
 impl UserInterface {
-    pub fn new() -> io::Result<Self> {
-        let mut interface = UserInterface {
-            lessons: parse_lessons(),
-            save: Save::load()?,
-            test_runner: TestRunner::new(),
-
-            last_attempt: None,
-        };
+    /// `lessons_dir` takes precedence over the one in the config file.
+    pub fn new(lessons_dir: Option<PathBuf>) -> io::Result<Self> {
+        let mut interface = UserInterface::with_save(Save::load()?, lessons_dir);
 
         interface.save();
 
         Ok(interface)
     }
 
+    fn with_save(save: Save, lessons_dir: Option<PathBuf>) -> Self {
+        let config = Config::load();
+        let lessons_dir = lessons_dir.or(config.lessons_dir.clone());
+
+        UserInterface {
+            lessons: parse_lessons(lessons_dir.as_deref()),
+            save,
+            test_runner: TestRunner::new(config.timeout()),
+
+            last_attempt: None,
+            interpreter_noted: false,
+        }
+    }
+
     pub fn run(&mut self) {
         self.show_progress_report();
 
@@ -93,6 +119,13 @@ impl UserInterface {
                         eprintln!("Operation failed.")
                     }
                 }
+                "watch" => {
+                    if let Some(next_lesson_id) = self.select_lesson(&self.save.unlocked_lessons) {
+                        self.watch_lesson(next_lesson_id);
+                    } else {
+                        eprintln!("Operation failed.")
+                    }
+                }
                 _ => unreachable!(),
             }
         }
@@ -156,12 +189,10 @@ impl UserInterface {
         }
     }
 
-    fn execute_lesson(&mut self, lesson_id: u16) {
-        self.print_lesson(lesson_id);
-
-        let lesson_data = &self.lessons.get(&lesson_id).unwrap().1;
+    fn savings_message(&self, lesson_id: u16) -> String {
+        let lesson_data = self.lesson_data(lesson_id);
 
-        let savings_message = format!(
+        format!(
             "Save {} byte{} to proceed. (target: {} bytes)",
             lesson_data.required_savings,
             if lesson_data.required_savings > 1 {
@@ -170,10 +201,14 @@ impl UserInterface {
                 ""
             },
             lesson_data.byte_threshold()
-        );
+        )
+    }
 
-        let boilerplate = self
-            .save
+    /// The last attempt at the lesson, or the starting program with some instructions.
+    fn attempt_text(&self, lesson_id: u16) -> String {
+        let lesson_data = self.lesson_data(lesson_id);
+
+        self.save
             .attempts
             .get(&lesson_id)
             .cloned()
@@ -190,12 +225,18 @@ impl UserInterface {
                     + "\n// Original Program:\n// "
                     + &lesson_data.starting_program.replace("\n", "\n// ")
                     + "\n\n// "
-                    + &savings_message
+                    + &self.savings_message(lesson_id)
                     + "\n\n"
                     + &lesson_data.starting_program
-            });
+            })
+    }
+
+    fn execute_lesson(&mut self, lesson_id: u16) {
+        self.print_lesson(lesson_id);
 
-        println!("{}", savings_message);
+        let boilerplate = self.attempt_text(lesson_id);
+
+        println!("{}", self.savings_message(lesson_id));
 
         if let Ok(true) = Confirm::new("Would you like to open your attempt?").with_default(true).prompt() {
             let result = edit(boilerplate);
@@ -204,46 +245,206 @@ impl UserInterface {
                     return;
                 }
 
-                self.save.attempts.insert(lesson_id, raw_text.clone());
+                self.attempt_lesson(lesson_id, raw_text);
+            }
+        }
+    }
 
-                let tokens_struct = process_submission(raw_text);
-                let tokens = tokens_struct.clone().collect::<Vec<_>>();
-                let byte_count: usize = byte_count(&tokens);
+    /// Records and grades an attempt, returning whether it completed the lesson.
+    fn attempt_lesson(&mut self, lesson_id: u16, raw_text: String) -> bool {
+        self.save.attempts.insert(lesson_id, raw_text.clone());
 
-                println!("{} tokens, {} bytes.", tokens.len(), byte_count);
+        if !self.interpreter_noted && !self.test_runner.uses_cemu() {
+            println!("No rom file was found, so attempts are tested with a built-in interpreter instead of CEmu. Place a TI84+CE rom file in the current directory to test with CEmu.");
+            self.interpreter_noted = true;
+        }
 
-                let byte_threshold = lesson_data.byte_threshold();
-                if byte_count > byte_threshold {
-                    println!("Too large: target is {} bytes", byte_threshold);
-                    self.last_attempt = Some(lesson_id);
-                } else {
-                    println!("Testing...");
-                    match self
-                        .test_runner
-                        .run_tests(tokens_struct, &lesson_data.tests)
-                    {
-                        Err(test_error) => {
-                            eprintln!("{}", test_error);
-                            std::process::exit(1)
-                        }
-
-                        Ok(ProgramTestResult::Fail(reason)) => {
-                            println!("{}", reason);
-                            self.last_attempt = Some(lesson_id);
-                        }
-                        Ok(ProgramTestResult::Pass) => {
-                            self.complete_lesson(lesson_id);
-                            self.last_attempt = None;
-                        }
-                    }
-                }
+        let passed = match self.check_submission(lesson_id, raw_text, OutputFormat::Text) {
+            // the attempt may well be right, it just can't be graded without CEmu, or it has a
+            // typo to fix
+            Err(test_error @ (TestError::Unsupported(_) | TestError::Untokenizable(_))) => {
+                println!("{}", test_error);
+                self.last_attempt = Some(lesson_id);
+                false
+            }
+            Err(test_error) => {
+                eprintln!("{}", test_error);
+                std::process::exit(1)
+            }
+
+            Ok(true) => {
+                self.complete_lesson(lesson_id);
+                self.last_attempt = None;
+                true
+            }
+            Ok(false) => {
+                self.last_attempt = Some(lesson_id);
+                false
+            }
+        };
+
+        self.save();
+
+        passed
+    }
+
+    /// Like [UserInterface::execute_lesson], but for editors which stay open: the attempt is
+    /// written to a file in [WORK_DIR] and graded every time it's saved, until it passes or the
+    /// user presses enter.
+    fn watch_lesson(&mut self, lesson_id: u16) {
+        self.print_lesson(lesson_id);
+        println!("{}", self.savings_message(lesson_id));
+
+        let path = PathBuf::from(WORK_DIR).join(format!("lesson{}.txt", lesson_id));
+        let created = fs::create_dir_all(WORK_DIR).and_then(|_| {
+            // the file is left alone if it's already there, as it may have unsaved progress
+            if fs::exists(&path)? {
+                Ok(())
+            } else {
+                fs::write(&path, self.attempt_text(lesson_id))
+            }
+        });
+
+        if let Err(error) = created {
+            eprintln!("Could not write {}: {}", path.display(), error);
+            return;
+        }
+
+        println!(
+            "Watching {}. Save it to test your attempt, or press enter to stop.",
+            path.display()
+        );
+
+        let (stop_sender, stop_receiver) = mpsc::channel();
+        let stdin_thread = thread::spawn(move || {
+            let _ = io::stdin().read_line(&mut String::new());
+            let _ = stop_sender.send(());
+        });
+
+        let mut last_modified = fs::metadata(&path).and_then(|metadata| metadata.modified()).ok();
+        let mut last_text = fs::read_to_string(&path).ok();
+
+        while stop_receiver.recv_timeout(WATCH_INTERVAL).is_err() {
+            let modified = fs::metadata(&path).and_then(|metadata| metadata.modified()).ok();
+            if modified == last_modified {
+                continue;
+            }
+            last_modified = modified;
+
+            let Ok(raw_text) = fs::read_to_string(&path) else {
+                continue;
+            };
+
+            // editors often write a file more than once when saving
+            if last_text.as_ref() == Some(&raw_text) || raw_text.trim() == "" {
+                continue;
+            }
+            last_text = Some(raw_text.clone());
+
+            println!();
+            if self.attempt_lesson(lesson_id, raw_text) {
+                println!("Press enter to continue.");
+                break;
+            }
+        }
+
+        // the thread only finishes once it's read a line, so that it can't swallow later input
+        let _ = stdin_thread.join();
+    }
+
+    /// Whether the submission is small enough and passes the lesson's tests, printing why not (or,
+    /// for JSON, a [SubmissionReport]).
+    fn check_submission(
+        &mut self,
+        lesson_id: u16,
+        raw_text: String,
+        format: OutputFormat,
+    ) -> Result<bool, TestError> {
+        let start = Instant::now();
+        let lesson_data = &self.lessons.get(&lesson_id).unwrap().1;
+
+        let tokens_struct = process_submission(raw_text).map_err(TestError::Untokenizable)?;
+        let tokens = tokens_struct.clone().collect::<Vec<_>>();
+        let byte_count: usize = byte_count(&tokens);
+        let byte_threshold = lesson_data.byte_threshold();
 
-                self.save();
+        if format == OutputFormat::Text {
+            println!("{} tokens, {} bytes.", tokens.len(), byte_count);
+        }
+
+        let (result, tests) = if byte_count > byte_threshold {
+            let too_large = format!("Too large: target is {} bytes", byte_threshold);
+            (ProgramTestResult::Fail(too_large), vec![])
+        } else {
+            if format == OutputFormat::Text {
+                println!("Testing...");
+            }
+            self.test_runner.run_tests(&tokens_struct, lesson_data)?
+        };
+
+        // only submissions which pass are timed
+        let speed = if result.passed() {
+            if format == OutputFormat::Text && lesson_data.speed.is_some() {
+                println!("Timing...");
+            }
+            self.test_runner.run_speed_test(&tokens_struct, lesson_data)?
+        } else {
+            None
+        };
+        let result = match &speed {
+            Some(speed) if !speed.result.passed() => speed.result.clone(),
+            _ => result,
+        };
+
+        let passed = result.passed();
+        match format {
+            OutputFormat::Text => {
+                if !passed {
+                    println!("{}", result);
+                } else if let Some(speed) = &speed {
+                    println!("{}", speed);
+                }
+            }
+            OutputFormat::Json => {
+                let report = SubmissionReport {
+                    lesson: lesson_id,
+                    tokens: tokens.len(),
+                    bytes: byte_count,
+                    threshold: byte_threshold,
+                    result,
+                    tests,
+                    speed,
+                    seconds: start.elapsed().as_secs_f64(),
+                };
+                println!("{}", serde_json::to_string(&report).unwrap());
             }
         }
+
+        Ok(passed)
     }
 
     fn complete_lesson(&mut self, lesson_id: u16) {
+        let new_lessons = self.unlock_lessons(lesson_id);
+
+        if !new_lessons.is_empty() {
+            println!(
+                "Congratulations! You unlocked {} new lesson{}:",
+                new_lessons.len(),
+                if new_lessons.len() > 1 { "s" } else { "" }
+            );
+
+            new_lessons
+                .iter()
+                .for_each(|&lesson_id| println!(" - {}", self.lesson_data(lesson_id).name));
+        } else {
+            println!("Congratulations!")
+        }
+
+        self.show_progress_report();
+    }
+
+    /// Marks the lesson completed, returning the lessons which that unlocks.
+    fn unlock_lessons(&mut self, lesson_id: u16) -> Vec<u16> {
         self.save.unlocked_lessons.remove(&lesson_id);
         self.save.completed_lessons.insert(lesson_id);
 
@@ -267,22 +468,9 @@ impl UserInterface {
             })
             .collect::<Vec<_>>();
 
-        if !new_lessons.is_empty() {
-            println!(
-                "Congratulations! You unlocked {} new lesson{}:",
-                new_lessons.len(),
-                if new_lessons.len() > 1 { "s" } else { "" }
-            );
-
-            new_lessons
-                .iter()
-                .for_each(|&lesson_id| println!(" - {}", self.lesson_data(lesson_id).name));
-        } else {
-            println!("Congratulations!")
-        }
-
         self.save.unlocked_lessons.extend(new_lessons.iter());
-        self.show_progress_report();
+
+        new_lessons
     }
 
     fn select_lesson(&self, set: &BTreeSet<u16>) -> Option<u16> {
@@ -320,6 +508,92 @@ impl UserInterface {
     }
 }
 
+/// `basiclings test`: grades a submission file, leaving the save file alone unless asked to
+/// record the attempt.
+pub fn run_test_command(options: &TestOptions, lessons_dir: Option<PathBuf>) -> ExitCode {
+    let save = if options.save {
+        match Save::load() {
+            Ok(save) => save,
+            Err(error) => {
+                eprintln!("Could not load the save file: {}", error);
+                return ExitCode::from(EXIT_ERROR);
+            }
+        }
+    } else {
+        Save::default()
+    };
+
+    let mut interface = UserInterface::with_save(save, lessons_dir);
+
+    if !interface.lessons.contains_key(&options.lesson) {
+        eprintln!("There is no lesson {}.", options.lesson);
+        return ExitCode::from(EXIT_ERROR);
+    }
+
+    let raw_text = match fs::read_to_string(&options.submission) {
+        Ok(raw_text) => raw_text,
+        Err(error) => {
+            eprintln!("Could not read {}: {}", options.submission.display(), error);
+            return ExitCode::from(EXIT_ERROR);
+        }
+    };
+
+    if options.save {
+        interface.save.attempts.insert(options.lesson, raw_text.clone());
+    }
+
+    let passed = match interface.check_submission(options.lesson, raw_text, options.format) {
+        Ok(passed) => passed,
+        Err(test_error) => {
+            match options.format {
+                OutputFormat::Text => eprintln!("{}", test_error),
+                OutputFormat::Json => println!(
+                    "{}",
+                    serde_json::json!({"lesson": options.lesson, "error": test_error.to_string().trim_end()})
+                ),
+            }
+            return ExitCode::from(EXIT_ERROR);
+        }
+    };
+
+    if passed {
+        match (options.save, options.format) {
+            (true, OutputFormat::Text) => interface.complete_lesson(options.lesson),
+            (true, OutputFormat::Json) => {
+                interface.unlock_lessons(options.lesson);
+            }
+            (false, OutputFormat::Text) => println!("{}", ProgramTestResult::Pass),
+            (false, OutputFormat::Json) => {}
+        }
+    }
+
+    if options.save {
+        interface.save();
+    }
+
+    if passed {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(EXIT_FAILED)
+    }
+}
+
+/// What `basiclings test --format json` prints.
+#[derive(Serialize)]
+struct SubmissionReport {
+    lesson: u16,
+    tokens: usize,
+    bytes: usize,
+    threshold: usize,
+    result: ProgramTestResult,
+    /// Empty if the submission was too large to be tested.
+    tests: Vec<TestReport>,
+    /// Only measured for lessons with a speed test, once the submission has passed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    speed: Option<SpeedReport>,
+    seconds: f64,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Save {
     pub unlocked_lessons: BTreeSet<u16>,
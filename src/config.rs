@@ -0,0 +1,35 @@
+use std::{fs, path::PathBuf, time::Duration};
+
+use serde::Deserialize;
+
+const CONFIG_PATH: &str = "basiclings_config.json";
+
+pub const DEFAULT_TIMEOUT_SECONDS: u64 = 30;
+
+/// Optional settings, read from `basiclings_config.json` in the current directory.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Config {
+    /// Seconds a program may run for in each case, unless a lesson sets its own timeout.
+    pub timeout: Option<u64>,
+    /// A directory of extra Markdown lessons, like `--lessons-dir`.
+    pub lessons_dir: Option<PathBuf>,
+}
+
+impl Config {
+    pub fn load() -> Config {
+        let Ok(data) = fs::read_to_string(CONFIG_PATH) else {
+            return Config::default();
+        };
+
+        serde_json::from_str(&data).unwrap_or_else(|err| {
+            eprintln!("Ignoring {}, which is malformed: {}", CONFIG_PATH, err);
+
+            Config::default()
+        })
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout.unwrap_or(DEFAULT_TIMEOUT_SECONDS))
+    }
+}
@@ -0,0 +1,2371 @@
+//! Runs TI-BASIC in-process, for testing submissions without a ROM.
+//!
+//! Only the part of the language the lessons need is covered; anything else stops the program
+//! with [Stop::Unsupported] rather than guessing at what the calculator would do.
+
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    f64::consts::{E, PI},
+    fmt::Display,
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+use titokens::{Token, Tokens};
+
+use crate::{
+    cemu::{CaseOutcome, Ending, ScreenCheck, TIEntry, TestBackend, TestCase, TestError},
+    keypad::Key,
+    lesson::{
+        Comparison, Output, Speed, Variable, VariableData, CUSTOM_LIST_PREFIX, MAX_MATRIX_DIMENSION,
+    },
+    screen::{text_characters, token_characters, HomeScreen, COLUMNS},
+    tools::{displayed_number, tokenize, tokenizer},
+};
+
+const STORE: Token = Token::OneByte(0x04);
+const OPEN_BRACKET: Token = Token::OneByte(0x06);
+const CLOSE_BRACKET: Token = Token::OneByte(0x07);
+const OPEN_BRACE: Token = Token::OneByte(0x08);
+const CLOSE_BRACE: Token = Token::OneByte(0x09);
+const OPEN_PAREN: Token = Token::OneByte(0x10);
+const CLOSE_PAREN: Token = Token::OneByte(0x11);
+const QUOTE: Token = Token::OneByte(0x2A);
+const COMMA: Token = Token::OneByte(0x2B);
+const EXPONENT: Token = Token::OneByte(0x3B);
+const COLON: Token = Token::OneByte(0x3E);
+const NEWLINE: Token = Token::OneByte(0x3F);
+const ANS: Token = Token::OneByte(0x72);
+const MULTIPLY: Token = Token::OneByte(0x82);
+const RAND: Token = Token::OneByte(0xAB);
+const GET_KEY: Token = Token::OneByte(0xAD);
+const NEGATE: Token = Token::OneByte(0xB0);
+const DIM: Token = Token::OneByte(0xB5);
+const SEQ: Token = Token::OneByte(0x23);
+const POWER: Token = Token::OneByte(0xF0);
+const CUSTOM_LIST: Token = Token::OneByte(0xEB);
+
+const THEN: Token = Token::OneByte(0xCF);
+const ELSE: Token = Token::OneByte(0xD0);
+const WHILE: Token = Token::OneByte(0xD1);
+const REPEAT: Token = Token::OneByte(0xD2);
+const FOR: Token = Token::OneByte(0xD3);
+const END: Token = Token::OneByte(0xD4);
+
+const MAX_LIST_LENGTH: usize = 999;
+
+/// A calculator error which stops the program, eg. `ERR:DOMAIN`. Lessons name them without
+/// the `ERR:`, eg. `"DIVIDE BY 0"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorKind {
+    Argument,
+    #[serde(rename = "DATA TYPE")]
+    DataType,
+    #[serde(rename = "DIM MISMATCH")]
+    DimMismatch,
+    #[serde(rename = "DIVIDE BY 0")]
+    DivideBy0,
+    Domain,
+    #[serde(rename = "INVALID DIM")]
+    InvalidDim,
+    Label,
+    #[serde(rename = "NONREAL ANS")]
+    Nonreal,
+    Overflow,
+    Syntax,
+    Undefined,
+}
+
+impl ErrorKind {
+    /// The error's number, which the calculator keeps in `errNo`.
+    pub fn code(&self) -> u8 {
+        match self {
+            ErrorKind::Overflow => 1,
+            ErrorKind::DivideBy0 => 2,
+            ErrorKind::Domain => 4,
+            ErrorKind::Nonreal => 7,
+            ErrorKind::Syntax => 8,
+            ErrorKind::DataType => 9,
+            ErrorKind::Argument => 10,
+            ErrorKind::DimMismatch => 11,
+            ErrorKind::InvalidDim => 12,
+            ErrorKind::Undefined => 13,
+            ErrorKind::Label => 20,
+        }
+    }
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ErrorKind::Argument => "ERR:ARGUMENT",
+            ErrorKind::DataType => "ERR:DATA TYPE",
+            ErrorKind::DimMismatch => "ERR:DIM MISMATCH",
+            ErrorKind::DivideBy0 => "ERR:DIVIDE BY 0",
+            ErrorKind::Domain => "ERR:DOMAIN",
+            ErrorKind::InvalidDim => "ERR:INVALID DIM",
+            ErrorKind::Label => "ERR:LABEL",
+            ErrorKind::Nonreal => "ERR:NONREAL ANS",
+            ErrorKind::Overflow => "ERR:OVERFLOW",
+            ErrorKind::Syntax => "ERR:SYNTAX",
+            ErrorKind::Undefined => "ERR:UNDEFINED",
+        })
+    }
+}
+
+/// Why a program stopped before reaching its end.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stop {
+    Error(ErrorKind),
+    /// The program used something the interpreter doesn't implement, named by its token.
+    Unsupported(String),
+    TimedOut,
+}
+
+impl From<ErrorKind> for Stop {
+    fn from(value: ErrorKind) -> Self {
+        Stop::Error(value)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Real(f64),
+    List(Vec<f64>),
+    /// Rows of a matrix.
+    Matrix(Vec<Vec<f64>>),
+    Str(Vec<Token>),
+    /// Only ever held by an equation variable; it is evaluated whenever the variable is used.
+    Equation(Vec<Token>),
+}
+
+/// What sort of value a variable may hold, going by its name.
+#[derive(PartialEq)]
+enum Kind {
+    Real,
+    List,
+    Matrix,
+    Str,
+    Equation,
+    Ans,
+}
+
+fn kind(name: &str) -> Kind {
+    if name == "Ans" {
+        Kind::Ans
+    } else if name.starts_with(CUSTOM_LIST_PREFIX) || (name.len() == 2 && name.starts_with('L')) {
+        Kind::List
+    } else if name.starts_with('[') {
+        Kind::Matrix
+    } else if name.starts_with("Str") {
+        Kind::Str
+    } else if name.starts_with('{') {
+        Kind::Equation
+    } else {
+        Kind::Real
+    }
+}
+
+/// The accessible name of every variable `tokens` mention outside of strings, eg. `A` and `L1`
+/// for `A+1->L1(2`.
+pub fn variable_names(tokens: &[Token]) -> BTreeSet<String> {
+    let mut parser = Parser::new(tokens);
+    let mut names = BTreeSet::new();
+    let mut in_string = false;
+
+    while let Some(token) = parser.peek() {
+        match token {
+            QUOTE => in_string = !in_string,
+            // strings don't need closing before either of these
+            STORE | NEWLINE => in_string = false,
+            ANS
+            | CUSTOM_LIST
+            | Token::OneByte(0x41..=0x5B)
+            | Token::TwoByte(0x5C | 0x5D | 0x5E | 0xAA, _)
+                if !in_string =>
+            {
+                // a custom list without a name is skipped over like any other token
+                if let Ok(name) = parser.variable_name() {
+                    names.insert(name);
+                }
+                continue;
+            }
+            _ => {}
+        }
+
+        parser.position += 1;
+    }
+
+    names
+}
+
+fn token_text(token: Token) -> String {
+    tokenizer().stringify(&[token]).to_string()
+}
+
+fn tokens_text(tokens: &[Token]) -> String {
+    Tokens::from_vec(tokens.to_vec(), None).to_string(tokenizer())
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Number(f64),
+    Str(Vec<Token>),
+    Variable(String),
+    List(Vec<Expr>),
+    Matrix(Vec<Vec<Expr>>),
+    /// eg. `L1(2)`, or `Ans(2)` which might instead be multiplication.
+    Index(String, Vec<Expr>),
+    Negate(Box<Expr>),
+    Postfix(Token, Box<Expr>),
+    Binary(Token, Box<Expr>, Box<Expr>),
+    Call(Token, Vec<Expr>),
+}
+
+#[derive(Clone, Debug)]
+enum Target {
+    Variable(String),
+    Index(String, Vec<Expr>),
+    Dim(String),
+    Rand,
+}
+
+#[derive(Clone, Debug)]
+enum Statement {
+    Empty,
+    Expression(Expr),
+    Store(Expr, Target),
+    If(Expr),
+    Then,
+    Else,
+    While(Expr),
+    Repeat(Expr),
+    For {
+        variable: String,
+        start: Expr,
+        end: Expr,
+        step: Option<Expr>,
+    },
+    End,
+    Return,
+    Lbl(Vec<Token>),
+    Goto(Vec<Token>),
+    Disp(Vec<Expr>),
+    Pause(Option<Expr>),
+    /// `Input "PROMPT",A`, or `Input A` which prompts with `?`.
+    Input(Option<Expr>, String),
+    Prompt(Vec<String>),
+    ClrHome,
+    /// Another statement may follow on the same line, eg. `DelVar ADelVar B`.
+    DelVar(String, Option<Box<Statement>>),
+    Sort(Token, String),
+}
+
+/// The precedence of binary operators, from `or` (loosest) to `nPr` (tightest). `^` and the
+/// postfix operators bind tighter than negation and are handled separately.
+fn binary_precedence(token: Token) -> Option<u8> {
+    match token {
+        Token::OneByte(0x3C | 0x3D) => Some(1),
+        Token::OneByte(0x40) => Some(2),
+        Token::OneByte(0x6A..=0x6F) => Some(3),
+        Token::OneByte(0x70 | 0x71) => Some(4),
+        Token::OneByte(0x82 | 0x83) => Some(5),
+        Token::OneByte(0x94 | 0x95) => Some(6),
+        _ => None,
+    }
+}
+
+fn is_postfix(token: Token) -> bool {
+    matches!(token, Token::OneByte(0x0C | 0x0D | 0x0F | 0x2D))
+}
+
+fn ends_expression(token: Option<Token>) -> bool {
+    match token {
+        None => true,
+        Some(token) => [STORE, COMMA, CLOSE_PAREN, CLOSE_BRACE, CLOSE_BRACKET].contains(&token),
+    }
+}
+
+/// Whether a block skipped over by `If`, `While` or `For(` needs its own `End`.
+fn opens_block(token: Token) -> bool {
+    [THEN, WHILE, REPEAT, FOR].contains(&token)
+}
+
+/// Splits a program into lines, leaving colons inside strings alone.
+fn split_lines(tokens: &[Token]) -> Vec<Vec<Token>> {
+    let mut lines = vec![vec![]];
+    let mut in_string = false;
+
+    for &token in tokens {
+        if token == NEWLINE || (token == COLON && !in_string) {
+            lines.push(vec![]);
+            in_string = false;
+            continue;
+        }
+
+        if token == QUOTE {
+            in_string = !in_string;
+        } else if token == STORE {
+            in_string = false;
+        }
+
+        lines.last_mut().unwrap().push(token);
+    }
+
+    lines
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(tokens: &'a [Token]) -> Self {
+        Parser {
+            tokens,
+            position: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<Token> {
+        self.tokens.get(self.position).copied()
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek();
+        self.position += 1;
+        token
+    }
+
+    fn eat(&mut self, token: Token) -> bool {
+        if self.peek() == Some(token) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn finish(&self) -> Result<(), Stop> {
+        if self.position < self.tokens.len() {
+            Err(ErrorKind::Syntax.into())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn statement(&mut self) -> Result<Statement, Stop> {
+        let Some(first) = self.peek() else {
+            return Ok(Statement::Empty);
+        };
+
+        let statement = match first {
+            Token::OneByte(0xCE) => {
+                self.position += 1;
+                Statement::If(self.expression()?)
+            }
+            THEN | ELSE | END | Token::OneByte(0xD5 | 0xD9 | 0xE1) => {
+                self.position += 1;
+                match first {
+                    THEN => Statement::Then,
+                    ELSE => Statement::Else,
+                    END => Statement::End,
+                    Token::OneByte(0xE1) => Statement::ClrHome,
+                    _ => Statement::Return,
+                }
+            }
+            WHILE => {
+                self.position += 1;
+                Statement::While(self.expression()?)
+            }
+            REPEAT => {
+                self.position += 1;
+                Statement::Repeat(self.expression()?)
+            }
+            FOR => {
+                self.position += 1;
+                let variable = self.variable_name()?;
+                if kind(&variable) != Kind::Real {
+                    return Err(ErrorKind::Syntax.into());
+                }
+
+                let mut arguments = vec![];
+                while self.eat(COMMA) {
+                    arguments.push(self.expression()?);
+                }
+                self.eat(CLOSE_PAREN);
+
+                let mut arguments = arguments.into_iter();
+                match (arguments.next(), arguments.next(), arguments.next()) {
+                    (Some(start), Some(end), step) if arguments.next().is_none() => {
+                        Statement::For {
+                            variable,
+                            start,
+                            end,
+                            step,
+                        }
+                    }
+                    _ => return Err(ErrorKind::Argument.into()),
+                }
+            }
+            Token::OneByte(0xD6 | 0xD7) => {
+                let label = self.tokens[1..].to_vec();
+                self.position = self.tokens.len();
+
+                if first == Token::OneByte(0xD6) {
+                    Statement::Lbl(label)
+                } else {
+                    Statement::Goto(label)
+                }
+            }
+            Token::OneByte(0xDE) => {
+                self.position += 1;
+                let mut arguments = vec![];
+                if self.peek().is_some() {
+                    arguments.push(self.expression()?);
+                    while self.eat(COMMA) {
+                        arguments.push(self.expression()?);
+                    }
+                }
+
+                Statement::Disp(arguments)
+            }
+            Token::OneByte(0xD8) => {
+                self.position += 1;
+                match self.peek() {
+                    Some(_) => Statement::Pause(Some(self.expression()?)),
+                    None => Statement::Pause(None),
+                }
+            }
+            Token::OneByte(0xDC) => {
+                self.position += 1;
+                if self.peek().is_none() {
+                    return Err(Stop::Unsupported("Input on the graph screen".to_owned()));
+                }
+
+                let start = self.position;
+                let prompt = self.expression()?;
+                if self.eat(COMMA) {
+                    Statement::Input(Some(prompt), self.variable_name()?)
+                } else {
+                    self.position = start;
+                    Statement::Input(None, self.variable_name()?)
+                }
+            }
+            Token::OneByte(0xDD) => {
+                self.position += 1;
+                let mut names = vec![self.variable_name()?];
+                while self.eat(COMMA) {
+                    names.push(self.variable_name()?);
+                }
+
+                Statement::Prompt(names)
+            }
+            Token::TwoByte(0xBB, 0x54) => {
+                self.position += 1;
+                let name = self.variable_name()?;
+                let rest = match self.peek() {
+                    Some(_) => Some(Box::new(self.statement()?)),
+                    None => None,
+                };
+
+                Statement::DelVar(name, rest)
+            }
+            Token::OneByte(0xE3 | 0xE4) => {
+                self.position += 1;
+                let name = self.variable_name()?;
+                self.eat(CLOSE_PAREN);
+
+                Statement::Sort(first, name)
+            }
+            _ => {
+                let expression = self.expression()?;
+                if self.eat(STORE) {
+                    Statement::Store(expression, self.target()?)
+                } else {
+                    Statement::Expression(expression)
+                }
+            }
+        };
+
+        self.finish()?;
+        Ok(statement)
+    }
+
+    fn target(&mut self) -> Result<Target, Stop> {
+        if self.eat(RAND) {
+            return Ok(Target::Rand);
+        }
+
+        if self.eat(DIM) {
+            let name = self.variable_name()?;
+            self.eat(CLOSE_PAREN);
+            return Ok(Target::Dim(name));
+        }
+
+        let name = self.variable_name()?;
+        if kind(&name) == Kind::Ans {
+            return Err(ErrorKind::Syntax.into());
+        }
+
+        if kind(&name) != Kind::Real && self.eat(OPEN_PAREN) {
+            Ok(Target::Index(name, self.arguments()?))
+        } else {
+            Ok(Target::Variable(name))
+        }
+    }
+
+    /// The accessible name of the variable at the current position, eg. `A`, `L1` or `|LSCORE`.
+    fn variable_name(&mut self) -> Result<String, Stop> {
+        let token = self.next().ok_or(ErrorKind::Syntax)?;
+
+        match token {
+            CUSTOM_LIST => {
+                let start = self.position;
+                if !self.peek().is_some_and(|token| token.is_alpha()) {
+                    return Err(ErrorKind::Syntax.into());
+                }
+
+                while self.position - start < 5
+                    && self.peek().is_some_and(|token| token.is_alphanumeric())
+                {
+                    self.position += 1;
+                }
+
+                Ok(
+                    CUSTOM_LIST_PREFIX.to_owned()
+                        + &tokens_text(&self.tokens[start..self.position]),
+                )
+            }
+            ANS | Token::OneByte(0x41..=0x5B) | Token::TwoByte(0x5C | 0x5D | 0x5E | 0xAA, _) => {
+                Ok(token_text(token))
+            }
+            _ => Err(ErrorKind::Syntax.into()),
+        }
+    }
+
+    fn expression(&mut self) -> Result<Expr, Stop> {
+        self.binary(1)
+    }
+
+    fn binary(&mut self, min_precedence: u8) -> Result<Expr, Stop> {
+        let mut left = self.unary()?;
+
+        loop {
+            let next = self.peek();
+            let (operator, precedence, implied) = match next.and_then(binary_precedence) {
+                Some(precedence) => (next.unwrap(), precedence, false),
+                None if ends_expression(next) => break,
+                None => (MULTIPLY, binary_precedence(MULTIPLY).unwrap(), true),
+            };
+
+            if precedence < min_precedence {
+                break;
+            }
+            if !implied {
+                self.position += 1;
+            }
+
+            let right = self.binary(precedence + 1)?;
+            left = Expr::Binary(operator, Box::new(left), Box::new(right));
+        }
+
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, Stop> {
+        if self.eat(NEGATE) {
+            return Ok(Expr::Negate(Box::new(self.unary()?)));
+        }
+
+        self.power()
+    }
+
+    fn power(&mut self) -> Result<Expr, Stop> {
+        let mut left = self.postfix()?;
+
+        while self.eat(POWER) {
+            let right = if self.eat(NEGATE) {
+                Expr::Negate(Box::new(self.postfix()?))
+            } else {
+                self.postfix()?
+            };
+
+            left = Expr::Binary(POWER, Box::new(left), Box::new(right));
+        }
+
+        Ok(left)
+    }
+
+    fn postfix(&mut self) -> Result<Expr, Stop> {
+        let mut operand = self.primary()?;
+
+        while let Some(token) = self.peek().filter(|&token| is_postfix(token)) {
+            self.position += 1;
+            operand = Expr::Postfix(token, Box::new(operand));
+        }
+
+        Ok(operand)
+    }
+
+    /// Comma-separated expressions up to an optional closing parenthesis.
+    fn arguments(&mut self) -> Result<Vec<Expr>, Stop> {
+        let mut arguments = vec![self.expression()?];
+        while self.eat(COMMA) {
+            arguments.push(self.expression()?);
+        }
+        self.eat(CLOSE_PAREN);
+
+        Ok(arguments)
+    }
+
+    fn primary(&mut self) -> Result<Expr, Stop> {
+        let token = self.peek().ok_or(ErrorKind::Syntax)?;
+
+        match token {
+            Token::OneByte(0x30..=0x3A) | EXPONENT => self.number(),
+            OPEN_PAREN => {
+                self.position += 1;
+                let expression = self.expression()?;
+                self.eat(CLOSE_PAREN);
+                Ok(expression)
+            }
+            OPEN_BRACE => {
+                self.position += 1;
+                let mut elements = vec![self.expression()?];
+                while self.eat(COMMA) {
+                    elements.push(self.expression()?);
+                }
+                self.eat(CLOSE_BRACE);
+                Ok(Expr::List(elements))
+            }
+            OPEN_BRACKET => {
+                self.position += 1;
+                let mut rows = vec![];
+                while self.eat(OPEN_BRACKET) {
+                    let mut row = vec![self.expression()?];
+                    while self.eat(COMMA) {
+                        row.push(self.expression()?);
+                    }
+                    self.eat(CLOSE_BRACKET);
+                    rows.push(row);
+                }
+                self.eat(CLOSE_BRACKET);
+
+                if rows.is_empty() {
+                    return Err(ErrorKind::Syntax.into());
+                }
+                Ok(Expr::Matrix(rows))
+            }
+            QUOTE => {
+                self.position += 1;
+                let start = self.position;
+                while self
+                    .peek()
+                    .is_some_and(|token| token != QUOTE && token != STORE)
+                {
+                    self.position += 1;
+                }
+                let text = self.tokens[start..self.position].to_vec();
+                self.eat(QUOTE);
+                Ok(Expr::Str(text))
+            }
+            RAND => {
+                self.position += 1;
+                if self.eat(OPEN_PAREN) {
+                    Ok(Expr::Call(RAND, self.arguments()?))
+                } else {
+                    Ok(Expr::Call(RAND, vec![]))
+                }
+            }
+            Token::OneByte(0xAC) => {
+                self.position += 1;
+                Ok(Expr::Number(PI))
+            }
+            Token::TwoByte(0xBB, 0x31) => {
+                self.position += 1;
+                Ok(Expr::Number(E))
+            }
+            GET_KEY => {
+                self.position += 1;
+                Ok(Expr::Call(GET_KEY, vec![]))
+            }
+            // the color tokens, BLUE to DARKGRAY, are the numbers 10 to 24
+            Token::TwoByte(0xEF, color @ 0x41..=0x4F) => {
+                self.position += 1;
+                Ok(Expr::Number((color - 0x41 + 10) as f64))
+            }
+            ANS
+            | CUSTOM_LIST
+            | Token::OneByte(0x41..=0x5B)
+            | Token::TwoByte(0x5C | 0x5D | 0x5E | 0xAA, _) => {
+                let name = self.variable_name()?;
+                if kind(&name) != Kind::Real && self.eat(OPEN_PAREN) {
+                    Ok(Expr::Index(name, self.arguments()?))
+                } else {
+                    Ok(Expr::Variable(name))
+                }
+            }
+            Token::OneByte(0x2C) => Err(Stop::Unsupported("complex numbers".to_owned())),
+            _ if ends_expression(Some(token))
+                || binary_precedence(token).is_some()
+                || is_postfix(token)
+                || token == POWER =>
+            {
+                Err(ErrorKind::Syntax.into())
+            }
+            _ if token_text(token).ends_with('(') => {
+                self.position += 1;
+                Ok(Expr::Call(token, self.arguments()?))
+            }
+            _ => Err(Stop::Unsupported(token_text(token))),
+        }
+    }
+
+    fn number(&mut self) -> Result<Expr, Stop> {
+        let mut text = String::new();
+        while let Some(Token::OneByte(byte @ 0x30..=0x3A)) = self.peek() {
+            text.push(if byte == 0x3A { '.' } else { byte as char });
+            self.position += 1;
+        }
+
+        if self.eat(EXPONENT) {
+            if text.is_empty() {
+                text.push('1');
+            }
+            text.push('e');
+            if self.eat(NEGATE) {
+                text.push('-');
+            }
+
+            let digits_start = text.len();
+            while let Some(Token::OneByte(byte @ 0x30..=0x39)) = self.peek() {
+                text.push(byte as char);
+                self.position += 1;
+            }
+            if text.len() == digits_start {
+                return Err(ErrorKind::Syntax.into());
+            }
+        }
+
+        let value = text.parse::<f64>().map_err(|_| ErrorKind::Syntax)?;
+        Ok(Expr::Number(checked(value)?))
+    }
+}
+
+/// Rounds to the 14 digits the calculator keeps, so that eg. `fPart(.1*30` is exactly zero.
+fn checked(value: f64) -> Result<f64, ErrorKind> {
+    if !value.is_finite() || value.abs() >= 1e100 {
+        return Err(ErrorKind::Overflow);
+    }
+
+    let rounded = format!("{:.13e}", value).parse::<f64>().unwrap();
+    Ok(if rounded.abs() < 1e-99 { 0.0 } else { rounded })
+}
+
+fn integer(value: f64) -> Result<i64, ErrorKind> {
+    if value.fract() == 0.0 {
+        Ok(value as i64)
+    } else {
+        Err(ErrorKind::Domain)
+    }
+}
+
+fn truthy(value: f64) -> f64 {
+    (value != 0.0) as u8 as f64
+}
+
+fn factorial(value: f64) -> Result<f64, ErrorKind> {
+    let n = integer(value)?;
+    if !(0..=69).contains(&n) {
+        return Err(ErrorKind::Domain);
+    }
+
+    checked((1..=n).map(|k| k as f64).product())
+}
+
+fn permutations(n: f64, r: f64) -> Result<f64, ErrorKind> {
+    let (n, r) = (integer(n)?, integer(r)?);
+    if n < 0 || r < 0 {
+        return Err(ErrorKind::Domain);
+    }
+    if r > n {
+        return Ok(0.0);
+    }
+
+    checked(((n - r + 1)..=n).map(|k| k as f64).product())
+}
+
+fn combinations(n: f64, r: f64) -> Result<f64, ErrorKind> {
+    let (n, r) = (integer(n)?, integer(r)?);
+    if n < 0 || r < 0 {
+        return Err(ErrorKind::Domain);
+    }
+    if r > n {
+        return Ok(0.0);
+    }
+
+    let r = r.min(n - r);
+    checked((0..r).fold(1.0, |total, k| total * (n - k) as f64 / (k + 1) as f64))
+}
+
+fn power(base: f64, exponent: f64) -> Result<f64, ErrorKind> {
+    if base == 0.0 && exponent <= 0.0 {
+        return Err(ErrorKind::Domain);
+    }
+    if base < 0.0 && exponent.fract() != 0.0 {
+        return Err(ErrorKind::Nonreal);
+    }
+
+    checked(base.powf(exponent))
+}
+
+fn gcd(a: f64, b: f64) -> Result<f64, ErrorKind> {
+    let (mut a, mut b) = (integer(a)?, integer(b)?);
+    if a < 0 || b < 0 {
+        return Err(ErrorKind::Domain);
+    }
+
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    Ok(a as f64)
+}
+
+fn binary_operation(operator: Token, a: f64, b: f64) -> Result<f64, ErrorKind> {
+    let result = match operator {
+        Token::OneByte(0x70) => a + b,
+        Token::OneByte(0x71) => a - b,
+        Token::OneByte(0x82) => a * b,
+        Token::OneByte(0x83) if b == 0.0 => return Err(ErrorKind::DivideBy0),
+        Token::OneByte(0x83) => a / b,
+        POWER => return power(a, b),
+        Token::OneByte(0x94) => return permutations(a, b),
+        Token::OneByte(0x95) => return combinations(a, b),
+        Token::OneByte(0x6A) => (a == b) as u8 as f64,
+        Token::OneByte(0x6B) => (a < b) as u8 as f64,
+        Token::OneByte(0x6C) => (a > b) as u8 as f64,
+        Token::OneByte(0x6D) => (a <= b) as u8 as f64,
+        Token::OneByte(0x6E) => (a >= b) as u8 as f64,
+        Token::OneByte(0x6F) => (a != b) as u8 as f64,
+        Token::OneByte(0x40) => (a != 0.0 && b != 0.0) as u8 as f64,
+        Token::OneByte(0x3C) => (a != 0.0 || b != 0.0) as u8 as f64,
+        Token::OneByte(0x3D) => ((a != 0.0) != (b != 0.0)) as u8 as f64,
+        _ => unreachable!("not a binary operator"),
+    };
+
+    checked(result)
+}
+
+/// Applies `operation` to numbers, or to each element of lists and matrices.
+fn map_numbers(
+    value: Value,
+    operation: impl Fn(f64) -> Result<f64, ErrorKind>,
+) -> Result<Value, Stop> {
+    Ok(match value {
+        Value::Real(x) => Value::Real(operation(x)?),
+        Value::List(list) => {
+            Value::List(list.into_iter().map(operation).collect::<Result<_, _>>()?)
+        }
+        Value::Matrix(rows) => Value::Matrix(
+            rows.into_iter()
+                .map(|row| row.into_iter().map(&operation).collect())
+                .collect::<Result<_, _>>()?,
+        ),
+        Value::Str(_) | Value::Equation(_) => return Err(ErrorKind::DataType.into()),
+    })
+}
+
+/// Applies `operation` to pairs of numbers, where lists of the same length pair up element by
+/// element and a number pairs with every element of a list or matrix.
+fn zip_numbers(
+    a: Value,
+    b: Value,
+    operation: impl Fn(f64, f64) -> Result<f64, ErrorKind>,
+) -> Result<Value, Stop> {
+    Ok(match (a, b) {
+        (Value::Real(a), b) => map_numbers(b, |b| operation(a, b))?,
+        (a, Value::Real(b)) => map_numbers(a, |a| operation(a, b))?,
+        (Value::List(a), Value::List(b)) => {
+            if a.len() != b.len() {
+                return Err(ErrorKind::DimMismatch.into());
+            }
+
+            Value::List(
+                a.into_iter()
+                    .zip(b)
+                    .map(|(a, b)| operation(a, b))
+                    .collect::<Result<_, _>>()?,
+            )
+        }
+        _ => return Err(ErrorKind::DataType.into()),
+    })
+}
+
+fn matrix_operation(operator: Token, a: Value, b: Value) -> Result<Value, Stop> {
+    let (Value::Matrix(a), Value::Matrix(b)) = (a, b) else {
+        return Err(ErrorKind::DataType.into());
+    };
+
+    match operator {
+        Token::OneByte(0x70 | 0x71) => {
+            if a.len() != b.len() || column_count(&a) != column_count(&b) {
+                return Err(ErrorKind::DimMismatch.into());
+            }
+
+            Ok(Value::Matrix(
+                a.into_iter()
+                    .zip(b)
+                    .map(|(a, b)| {
+                        a.into_iter()
+                            .zip(b)
+                            .map(|(a, b)| binary_operation(operator, a, b))
+                            .collect()
+                    })
+                    .collect::<Result<_, _>>()?,
+            ))
+        }
+        MULTIPLY => {
+            if column_count(&a) != b.len() {
+                return Err(ErrorKind::DimMismatch.into());
+            }
+
+            Ok(Value::Matrix(
+                a.iter()
+                    .map(|row| {
+                        (0..column_count(&b))
+                            .map(|column| {
+                                checked(
+                                    row.iter().zip(&b).map(|(x, b_row)| x * b_row[column]).sum(),
+                                )
+                            })
+                            .collect()
+                    })
+                    .collect::<Result<_, _>>()?,
+            ))
+        }
+        _ => Err(ErrorKind::DataType.into()),
+    }
+}
+
+fn real(value: Value) -> Result<f64, Stop> {
+    match value {
+        Value::Real(x) => Ok(x),
+        _ => Err(ErrorKind::DataType.into()),
+    }
+}
+
+fn list(value: Value) -> Result<Vec<f64>, Stop> {
+    match value {
+        Value::List(list) => Ok(list),
+        _ => Err(ErrorKind::DataType.into()),
+    }
+}
+
+fn string(value: Value) -> Result<Vec<Token>, Stop> {
+    match value {
+        Value::Str(tokens) => Ok(tokens),
+        _ => Err(ErrorKind::DataType.into()),
+    }
+}
+
+/// A 1-based index into something of length `length`.
+fn index(value: f64, length: usize) -> Result<usize, ErrorKind> {
+    let index = integer(value).map_err(|_| ErrorKind::InvalidDim)?;
+    if index < 1 || index as usize > length {
+        return Err(ErrorKind::InvalidDim);
+    }
+
+    Ok(index as usize - 1)
+}
+
+/// The number of rows or columns of a matrix, which can't be empty.
+fn matrix_dimension(value: f64) -> Result<usize, ErrorKind> {
+    let dimension = integer(value).map_err(|_| ErrorKind::InvalidDim)?;
+    if !(1..=MAX_MATRIX_DIMENSION as i64).contains(&dimension) {
+        return Err(ErrorKind::InvalidDim);
+    }
+
+    Ok(dimension as usize)
+}
+
+fn column_count(rows: &[Vec<f64>]) -> usize {
+    rows.first().map_or(0, Vec::len)
+}
+
+/// Checks that `rows` have the shape of a matrix, ie. that there's at least one and they're all
+/// the same length.
+fn matrix(rows: Vec<Vec<f64>>) -> Result<Value, ErrorKind> {
+    let columns = column_count(&rows);
+    if rows.len() > MAX_MATRIX_DIMENSION
+        || !(1..=MAX_MATRIX_DIMENSION).contains(&columns)
+        || rows.iter().any(|row| row.len() != columns)
+    {
+        return Err(ErrorKind::InvalidDim);
+    }
+
+    Ok(Value::Matrix(rows))
+}
+
+fn list_length(value: f64) -> Result<usize, ErrorKind> {
+    let length = integer(value).map_err(|_| ErrorKind::InvalidDim)?;
+    if !(0..=MAX_LIST_LENGTH as i64).contains(&length) {
+        return Err(ErrorKind::InvalidDim);
+    }
+
+    Ok(length as usize)
+}
+
+/// L'Ecuyer's combined generator, which the calculator uses for `rand`. The calculator's decimal
+/// arithmetic and its own ways of drawing integers give different numbers for the same seed, so
+/// seeded cases are only compared against the starting program.
+struct Random {
+    first: i64,
+    second: i64,
+}
+
+impl Random {
+    fn seeded(seed: f64) -> Self {
+        let seed = seed.abs().trunc() as i64;
+        if seed == 0 {
+            Random {
+                first: 12345,
+                second: 67890,
+            }
+        } else {
+            Random {
+                first: (40014 * seed) % 2147483563,
+                second: seed % 2147483399,
+            }
+        }
+    }
+
+    fn next(&mut self) -> f64 {
+        self.first = (40014 * self.first) % 2147483563;
+        self.second = (40692 * self.second) % 2147483399;
+
+        let mut result = self.first - self.second;
+        if result < 1 {
+            result += 2147483562;
+        }
+
+        result as f64 * 4.656613e-10
+    }
+
+    /// A whole number from `low` to `high` inclusive.
+    fn integer(&mut self, low: i64, high: i64) -> f64 {
+        let (low, high) = (low.min(high), low.max(high));
+        (low + ((high - low + 1) as f64 * self.next()) as i64) as f64
+    }
+}
+
+/// Where control flow returns to at the matching `End`.
+enum Block {
+    Then,
+    While(usize),
+    Repeat(usize),
+    For {
+        line: usize,
+        variable: String,
+        end: f64,
+        step: f64,
+    },
+}
+
+/// The line of the `End` (or, if `stop_at_else`, the `Else`) which closes the block opened
+/// before `from`, and whether it's an `Else`.
+fn find_block_end(lines: &[Vec<Token>], from: usize, stop_at_else: bool) -> Option<(usize, bool)> {
+    let mut depth = 0;
+
+    for (index, line) in lines.iter().enumerate().skip(from) {
+        match line.first() {
+            Some(&token) if opens_block(token) => depth += 1,
+            Some(&END) if depth == 0 => return Some((index, false)),
+            Some(&END) => depth -= 1,
+            Some(&ELSE) if depth == 0 && stop_at_else => return Some((index, true)),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+pub struct Interpreter {
+    variables: HashMap<String, Value>,
+    random: Random,
+    screen: HomeScreen,
+    /// Keys which will be pressed, in order.
+    keys: VecDeque<&'static Key>,
+    deadline: Instant,
+}
+
+impl Interpreter {
+    pub fn new(deadline: Instant) -> Self {
+        Interpreter {
+            variables: HashMap::from([("Ans".to_owned(), Value::Real(0.0))]),
+            random: Random::seeded(0.0),
+            screen: HomeScreen::default(),
+            keys: VecDeque::new(),
+            deadline,
+        }
+    }
+
+    /// Like `seed->rand`.
+    pub fn seed_random(&mut self, seed: f64) {
+        self.random = Random::seeded(seed);
+    }
+
+    pub fn set_variable(&mut self, variable: &Variable) -> Result<(), Stop> {
+        let value = match &variable.value {
+            VariableData::String(text) => Value::Str(tokenize(text).collect()),
+            VariableData::Equation { equation } => Value::Equation(tokenize(equation).collect()),
+            VariableData::RealMatrix(rows) => matrix(rows.clone())?,
+            VariableData::RealList(list) => Value::List(list.clone()),
+            VariableData::RealNumber(number) => Value::Real(*number),
+            VariableData::ComplexList(_) | VariableData::ComplexNumber(_) => {
+                return Err(Stop::Unsupported("complex numbers".to_owned()))
+            }
+        };
+
+        self.variables.insert(variable.name.clone(), value);
+        Ok(())
+    }
+
+    /// Queues keys for `getKey`, `Input` and `Prompt` to read.
+    pub fn press_keys(&mut self, keys: &[&'static Key]) {
+        self.keys.extend(keys);
+    }
+
+    pub fn screen(&self) -> &HomeScreen {
+        &self.screen
+    }
+
+    /// The value of a variable, if it exists.
+    pub fn variable(&self, name: &str) -> Option<VariableData> {
+        Some(match self.variables.get(name)? {
+            Value::Real(x) => VariableData::RealNumber(*x),
+            Value::List(list) => VariableData::RealList(list.clone()),
+            Value::Matrix(rows) => VariableData::RealMatrix(rows.clone()),
+            Value::Str(tokens) => VariableData::String(tokens_text(tokens)),
+            Value::Equation(tokens) => VariableData::Equation {
+                equation: tokens_text(tokens),
+            },
+        })
+    }
+
+    /// Shows a value on the home screen as `Disp` does.
+    fn display(&mut self, value: &Value) -> Result<(), Stop> {
+        let (characters, align_right) = self.value_characters(value)?;
+        self.display_line(&characters, align_right)
+    }
+
+    fn display_line(&mut self, characters: &[u8], align_right: bool) -> Result<(), Stop> {
+        if !self.screen.display(characters, align_right) {
+            return Err(Stop::Unsupported(format!(
+                "displaying lines longer than {} characters",
+                COLUMNS
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// What `Disp` shows for a value, and whether it's right-aligned.
+    fn value_characters(&self, value: &Value) -> Result<(Vec<u8>, bool), Stop> {
+        let (characters, align_right) = match value {
+            Value::Real(x) => (text_characters(&displayed_number(*x)), true),
+            Value::List(list) => {
+                let elements = list
+                    .iter()
+                    .map(|&x| displayed_number(x))
+                    .collect::<Vec<_>>();
+
+                (
+                    text_characters(&format!("{{{}}}", elements.join(" "))),
+                    true,
+                )
+            }
+            Value::Str(tokens) => (
+                tokens
+                    .iter()
+                    .map(|&token| {
+                        token_characters(&token_text(token))
+                            .ok_or_else(|| format!("displaying {}", token_text(token)))
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(|characters| characters.concat()),
+                false,
+            ),
+            Value::Matrix(_) => return Err(Stop::Unsupported("displaying matrices".to_owned())),
+            Value::Equation(_) => return Err(Stop::Unsupported("displaying equations".to_owned())),
+        };
+
+        Ok((characters.map_err(Stop::Unsupported)?, align_right))
+    }
+
+    /// Types keys after the prompt until enter is pressed, then stores what was typed. Strings
+    /// are stored as typed, and anything else is evaluated first.
+    fn input(&mut self, prompt: Vec<u8>, name: &str) -> Result<(), Stop> {
+        let mut typed = String::new();
+        loop {
+            // the calculator would wait forever
+            let key = self.keys.pop_front().ok_or(Stop::TimedOut)?;
+            if key.is_enter() {
+                break;
+            }
+
+            match key.typed {
+                Some(text) => typed += text,
+                None => return Err(Stop::Unsupported(format!("pressing {} at an Input", key))),
+            }
+        }
+
+        let tokens = tokenizer()
+            .tokenize(&typed)
+            .map_err(|_| ErrorKind::Syntax)?
+            .0
+            .collect::<Vec<_>>();
+        let typed_characters = text_characters(&typed).map_err(Stop::Unsupported)?;
+        self.display_line(&[prompt, typed_characters].concat(), false)?;
+
+        let value = if kind(name) == Kind::Str {
+            Value::Str(tokens)
+        } else {
+            self.evaluate_tokens(&tokens)?
+        };
+
+        self.store(&Target::Variable(name.to_owned()), value)
+    }
+
+    fn check_deadline(&self) -> Result<(), Stop> {
+        if Instant::now() > self.deadline {
+            Err(Stop::TimedOut)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Runs a program to its end, or until it stops early.
+    pub fn run(&mut self, program: &[Token]) -> Result<(), Stop> {
+        let lines = split_lines(program);
+        let statements = lines
+            .iter()
+            .map(|line| Parser::new(line).statement())
+            .collect::<Vec<_>>();
+
+        let mut blocks: Vec<Block> = vec![];
+        let mut line = 0;
+
+        'lines: while line < lines.len() {
+            self.check_deadline()?;
+
+            let mut statement = statements[line].as_ref().map_err(Stop::clone)?;
+            line += 1;
+
+            while let Statement::DelVar(name, rest) = statement {
+                self.variables.remove(name);
+                match rest {
+                    Some(rest) => statement = rest,
+                    None => continue 'lines,
+                }
+            }
+
+            match statement {
+                Statement::Empty | Statement::Lbl(_) => {}
+                Statement::ClrHome => self.screen.clear(),
+                Statement::Expression(expression) => {
+                    let value = self.evaluate(expression)?;
+                    self.variables.insert("Ans".to_owned(), value);
+                }
+                Statement::Store(expression, target) => {
+                    let value = self.evaluate(expression)?;
+                    self.store(target, value.clone())?;
+                    self.variables.insert("Ans".to_owned(), value);
+                }
+                Statement::If(condition) => {
+                    let condition = self.condition(condition)?;
+
+                    if lines.get(line).and_then(|line| line.first()) != Some(&THEN) {
+                        if !condition {
+                            line += 1;
+                        }
+                        continue;
+                    }
+
+                    line += 1;
+                    if condition {
+                        blocks.push(Block::Then);
+                    } else {
+                        match find_block_end(&lines, line, true) {
+                            Some((end, is_else)) => {
+                                if is_else {
+                                    blocks.push(Block::Then);
+                                }
+                                line = end + 1;
+                            }
+                            None => return Ok(()),
+                        }
+                    }
+                }
+                Statement::Then => return Err(ErrorKind::Syntax.into()),
+                // only reached at the end of a Then branch which was run
+                Statement::Else => match find_block_end(&lines, line, false) {
+                    Some((end, _)) => {
+                        blocks.pop();
+                        line = end + 1;
+                    }
+                    None => return Ok(()),
+                },
+                Statement::While(condition) => {
+                    if self.condition(condition)? {
+                        blocks.push(Block::While(line - 1));
+                    } else {
+                        match find_block_end(&lines, line, false) {
+                            Some((end, _)) => line = end + 1,
+                            None => return Ok(()),
+                        }
+                    }
+                }
+                Statement::Repeat(_) => blocks.push(Block::Repeat(line - 1)),
+                Statement::For {
+                    variable,
+                    start,
+                    end,
+                    step,
+                } => {
+                    let start = real(self.evaluate(start)?)?;
+                    let end = real(self.evaluate(end)?)?;
+                    let step = match step {
+                        Some(step) => real(self.evaluate(step)?)?,
+                        None => 1.0,
+                    };
+
+                    self.variables.insert(variable.clone(), Value::Real(start));
+
+                    if (step >= 0.0 && start <= end) || (step < 0.0 && start >= end) {
+                        blocks.push(Block::For {
+                            line: line - 1,
+                            variable: variable.clone(),
+                            end,
+                            step,
+                        });
+                    } else {
+                        match find_block_end(&lines, line, false) {
+                            Some((end, _)) => line = end + 1,
+                            None => return Ok(()),
+                        }
+                    }
+                }
+                Statement::End => match blocks.pop().ok_or(ErrorKind::Syntax)? {
+                    Block::Then => {}
+                    Block::While(start) => line = start,
+                    Block::Repeat(start) => {
+                        let Ok(Statement::Repeat(condition)) = &statements[start] else {
+                            unreachable!("Repeat blocks start at a Repeat");
+                        };
+
+                        if !self.condition(condition)? {
+                            blocks.push(Block::Repeat(start));
+                            line = start + 1;
+                        }
+                    }
+                    Block::For {
+                        line: start,
+                        variable,
+                        end,
+                        step,
+                    } => {
+                        let current = match self.variables.get(&variable) {
+                            Some(Value::Real(x)) => *x,
+                            _ => 0.0,
+                        };
+                        let next = checked(current + step)?;
+                        self.variables.insert(variable.clone(), Value::Real(next));
+
+                        if (step >= 0.0 && next <= end) || (step < 0.0 && next >= end) {
+                            blocks.push(Block::For {
+                                line: start,
+                                variable,
+                                end,
+                                step,
+                            });
+                            line = start + 1;
+                        }
+                    }
+                },
+                Statement::Return => return Ok(()),
+                Statement::Goto(label) => {
+                    line = statements
+                        .iter()
+                        .position(|statement| {
+                            matches!(statement, Ok(Statement::Lbl(other)) if other == label)
+                        })
+                        .ok_or(ErrorKind::Label)?
+                        + 1;
+                }
+                Statement::Disp(arguments) => {
+                    for argument in arguments {
+                        let value = self.evaluate(argument)?;
+                        self.display(&value)?;
+                    }
+                }
+                Statement::Pause(argument) => {
+                    if let Some(argument) = argument {
+                        let value = self.evaluate(argument)?;
+                        self.display(&value)?;
+                    }
+                }
+                Statement::Input(prompt, name) => {
+                    let prompt = match prompt {
+                        Some(prompt) => {
+                            let value = self.evaluate(prompt)?;
+                            self.value_characters(&value)?.0
+                        }
+                        None => b"?".to_vec(),
+                    };
+
+                    self.input(prompt, name)?;
+                }
+                Statement::Prompt(names) => {
+                    for name in names {
+                        let prompt =
+                            text_characters(&format!("{}=?", name)).map_err(Stop::Unsupported)?;
+                        self.input(prompt, name)?;
+                    }
+                }
+                Statement::Sort(command, name) => {
+                    let mut list = list(self.variable_value(name)?)?;
+                    list.sort_by(|a, b| a.total_cmp(b));
+                    if *command == Token::OneByte(0xE4) {
+                        list.reverse();
+                    }
+                    self.variables.insert(name.clone(), Value::List(list));
+                }
+                Statement::DelVar(..) => unreachable!("handled above"),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn condition(&mut self, condition: &Expr) -> Result<bool, Stop> {
+        Ok(real(self.evaluate(condition)?)? != 0.0)
+    }
+
+    fn variable_value(&mut self, name: &str) -> Result<Value, Stop> {
+        match self.variables.get(name) {
+            Some(Value::Equation(tokens)) => {
+                let tokens = tokens.clone();
+                self.evaluate_tokens(&tokens)
+            }
+            Some(value) => Ok(value.clone()),
+            None if kind(name) == Kind::Real => Ok(Value::Real(0.0)),
+            None => Err(ErrorKind::Undefined.into()),
+        }
+    }
+
+    /// Evaluates the text of a string or equation as an expression.
+    fn evaluate_tokens(&mut self, tokens: &[Token]) -> Result<Value, Stop> {
+        let mut parser = Parser::new(tokens);
+        let expression = parser.expression()?;
+        parser.finish()?;
+
+        self.evaluate(&expression)
+    }
+
+    fn store(&mut self, target: &Target, value: Value) -> Result<(), Stop> {
+        match target {
+            Target::Variable(name) => {
+                let value = match (kind(name), value) {
+                    (Kind::Real, value @ Value::Real(_))
+                    | (Kind::List, value @ Value::List(_))
+                    | (Kind::Matrix, value @ Value::Matrix(_))
+                    | (Kind::Str, value @ Value::Str(_)) => value,
+                    (Kind::Equation, Value::Str(tokens)) => Value::Equation(tokens),
+                    _ => return Err(ErrorKind::DataType.into()),
+                };
+
+                self.variables.insert(name.clone(), value);
+            }
+            Target::Index(name, indices) => {
+                let value = real(value)?;
+                let indices = indices
+                    .iter()
+                    .map(|index| self.evaluate(index).and_then(real))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                match (self.variables.get_mut(name), indices.as_slice()) {
+                    (Some(Value::List(list)), &[position]) => {
+                        let position = index(position, (list.len() + 1).min(MAX_LIST_LENGTH))?;
+                        if position == list.len() {
+                            list.push(value);
+                        } else {
+                            list[position] = value;
+                        }
+                    }
+                    (Some(Value::Matrix(rows)), &[row, column]) => {
+                        let row = index(row, rows.len())?;
+                        let column = index(column, rows[row].len())?;
+                        rows[row][column] = value;
+                    }
+                    (None, _) if kind(name) == Kind::List => {
+                        return Err(ErrorKind::Undefined.into())
+                    }
+                    (Some(Value::List(_) | Value::Matrix(_)), _) => {
+                        return Err(ErrorKind::InvalidDim.into())
+                    }
+                    _ => return Err(ErrorKind::Syntax.into()),
+                }
+            }
+            Target::Dim(name) => match (kind(name), value) {
+                (Kind::List, Value::Real(length)) => {
+                    let length = list_length(length)?;
+                    let mut list = match self.variables.remove(name) {
+                        Some(Value::List(list)) => list,
+                        _ => vec![],
+                    };
+                    list.resize(length, 0.0);
+                    self.variables.insert(name.clone(), Value::List(list));
+                }
+                (Kind::Matrix, Value::List(dimensions)) if dimensions.len() == 2 => {
+                    let (row_count, column_count) = (
+                        matrix_dimension(dimensions[0])?,
+                        matrix_dimension(dimensions[1])?,
+                    );
+                    let mut rows = match self.variables.remove(name) {
+                        Some(Value::Matrix(rows)) => rows,
+                        _ => vec![],
+                    };
+                    rows.resize(row_count, vec![]);
+                    for row in &mut rows {
+                        row.resize(column_count, 0.0);
+                    }
+                    self.variables.insert(name.clone(), Value::Matrix(rows));
+                }
+                _ => return Err(ErrorKind::DataType.into()),
+            },
+            Target::Rand => self.random = Random::seeded(real(value)?),
+        }
+
+        Ok(())
+    }
+
+    fn evaluate(&mut self, expression: &Expr) -> Result<Value, Stop> {
+        match expression {
+            Expr::Number(x) => Ok(Value::Real(*x)),
+            Expr::Str(tokens) => Ok(Value::Str(tokens.clone())),
+            Expr::Variable(name) => self.variable_value(name),
+            Expr::List(elements) => {
+                let list = elements
+                    .iter()
+                    .map(|element| self.evaluate(element).and_then(real))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                Ok(Value::List(list))
+            }
+            Expr::Matrix(rows) => {
+                let rows = rows
+                    .iter()
+                    .map(|row| {
+                        row.iter()
+                            .map(|element| self.evaluate(element).and_then(real))
+                            .collect::<Result<Vec<_>, _>>()
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                Ok(matrix(rows)?)
+            }
+            Expr::Index(name, indices) => {
+                let indices = indices
+                    .iter()
+                    .map(|index| self.evaluate(index))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                if let Some(Value::Equation(tokens)) = self.variables.get(name) {
+                    // Y1(2) is Y1 evaluated at X=2
+                    let tokens = tokens.clone();
+                    let [argument] =
+                        <[Value; 1]>::try_from(indices).map_err(|_| ErrorKind::Argument)?;
+                    let previous_x = self
+                        .variables
+                        .insert("X".to_owned(), Value::Real(real(argument)?));
+                    let result = self.evaluate_tokens(&tokens);
+                    match previous_x {
+                        Some(x) => self.variables.insert("X".to_owned(), x),
+                        None => self.variables.remove("X"),
+                    };
+
+                    return result;
+                }
+
+                match (self.variable_value(name)?, indices.as_slice()) {
+                    (Value::List(list), [Value::Real(position)]) => {
+                        Ok(Value::Real(list[index(*position, list.len())?]))
+                    }
+                    (Value::Matrix(rows), [Value::Real(row), Value::Real(column)]) => {
+                        let row = index(*row, rows.len())?;
+                        Ok(Value::Real(rows[row][index(*column, rows[row].len())?]))
+                    }
+                    // Ans(2) when Ans isn't a list is implied multiplication
+                    (value @ Value::Real(_), [factor]) if name == "Ans" => {
+                        zip_numbers(value, factor.clone(), |a, b| {
+                            binary_operation(MULTIPLY, a, b)
+                        })
+                    }
+                    (Value::List(_) | Value::Matrix(_), _) => Err(ErrorKind::InvalidDim.into()),
+                    _ => Err(ErrorKind::DataType.into()),
+                }
+            }
+            Expr::Negate(operand) => map_numbers(self.evaluate(operand)?, |x| Ok(-x)),
+            Expr::Postfix(operator, operand) => {
+                let operand = self.evaluate(operand)?;
+                match operator {
+                    Token::OneByte(0x0C) => {
+                        map_numbers(operand, |x| binary_operation(Token::OneByte(0x83), 1.0, x))
+                    }
+                    Token::OneByte(0x0D) => map_numbers(operand, |x| checked(x * x)),
+                    Token::OneByte(0x0F) => map_numbers(operand, |x| checked(x * x * x)),
+                    _ => map_numbers(operand, factorial),
+                }
+            }
+            Expr::Binary(operator, left, right) => {
+                let left = self.evaluate(left)?;
+                let right = self.evaluate(right)?;
+
+                match (left, right) {
+                    (Value::Str(a), Value::Str(b)) => match operator {
+                        Token::OneByte(0x70) => Ok(Value::Str([a, b].concat())),
+                        Token::OneByte(0x6A) => Ok(Value::Real((a == b) as u8 as f64)),
+                        Token::OneByte(0x6F) => Ok(Value::Real((a != b) as u8 as f64)),
+                        _ => Err(ErrorKind::DataType.into()),
+                    },
+                    (left @ Value::Matrix(_), right @ Value::Matrix(_)) => {
+                        matrix_operation(*operator, left, right)
+                    }
+                    (left, right) => {
+                        zip_numbers(left, right, |a, b| binary_operation(*operator, a, b))
+                    }
+                }
+            }
+            Expr::Call(function, arguments) => self.call(*function, arguments),
+        }
+    }
+
+    fn call(&mut self, function: Token, arguments: &[Expr]) -> Result<Value, Stop> {
+        if function == SEQ {
+            return self.sequence(arguments);
+        }
+
+        let mut arguments = arguments
+            .iter()
+            .map(|argument| self.evaluate(argument))
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter();
+        let count = arguments.len();
+        let mut argument = || arguments.next().ok_or(Stop::Error(ErrorKind::Argument));
+
+        let value = match function {
+            // abs(, int(, iPart(, fPart(, not(
+            Token::OneByte(0xB2) => map_numbers(argument()?, |x| Ok(x.abs()))?,
+            Token::OneByte(0xB1) => map_numbers(argument()?, |x| Ok(x.floor()))?,
+            Token::OneByte(0xB9) => map_numbers(argument()?, |x| Ok(x.trunc()))?,
+            Token::OneByte(0xBA) => map_numbers(argument()?, |x| checked(x.fract()))?,
+            Token::OneByte(0xB8) => map_numbers(argument()?, |x| Ok(1.0 - truthy(x)))?,
+            // √(, ln(, log(, e^(, 10^(
+            Token::OneByte(0xBC) => map_numbers(argument()?, |x| {
+                if x < 0.0 {
+                    Err(ErrorKind::Nonreal)
+                } else {
+                    checked(x.sqrt())
+                }
+            })?,
+            Token::OneByte(0xBE | 0xC0) => {
+                let base = if function == Token::OneByte(0xBE) {
+                    E
+                } else {
+                    10.0
+                };
+                map_numbers(argument()?, |x| logarithm(x, base))?
+            }
+            Token::OneByte(0xBF) => map_numbers(argument()?, |x| checked(x.exp()))?,
+            Token::OneByte(0xC1) => map_numbers(argument()?, |x| power(10.0, x))?,
+            // sin(, cos(, tan(, in radians
+            Token::OneByte(0xC2) => map_numbers(argument()?, |x| checked(x.sin()))?,
+            Token::OneByte(0xC4) => map_numbers(argument()?, |x| checked(x.cos()))?,
+            Token::OneByte(0xC6) => map_numbers(argument()?, |x| checked(x.tan()))?,
+            // logBASE(
+            Token::TwoByte(0xEF, 0x34) => zip_numbers(argument()?, argument()?, |x, base| {
+                checked(logarithm(x, E)? / logarithm(base, E)?)
+            })?,
+            // round(
+            Token::OneByte(0x12) => {
+                let value = argument()?;
+                let places = if count > 1 { real(argument()?)? } else { 9.0 };
+                let places = integer(places)?;
+                if !(0..=9).contains(&places) {
+                    return Err(ErrorKind::Domain.into());
+                }
+
+                let scale = 10f64.powi(places as i32);
+                map_numbers(value, |x| checked((x * scale).round() / scale))?
+            }
+            // remainder(, gcd(, lcm(
+            Token::TwoByte(0xEF, 0x32) => zip_numbers(argument()?, argument()?, |a, b| {
+                if b == 0.0 {
+                    return Err(ErrorKind::DivideBy0);
+                }
+                checked(a - b * (a / b).floor())
+            })?,
+            Token::TwoByte(0xBB, 0x09) => zip_numbers(argument()?, argument()?, gcd)?,
+            Token::TwoByte(0xBB, 0x08) => zip_numbers(argument()?, argument()?, |a, b| {
+                let divisor = gcd(a, b)?;
+                if divisor == 0.0 {
+                    Ok(0.0)
+                } else {
+                    checked(a / divisor * b)
+                }
+            })?,
+            // max(, min(
+            Token::OneByte(0x19 | 0x1A) => {
+                let maximum = function == Token::OneByte(0x19);
+                let pick = move |a: f64, b: f64| Ok(if (a > b) == maximum { a } else { b });
+
+                if count == 1 {
+                    let list = list(argument()?)?;
+                    if list.is_empty() {
+                        return Err(ErrorKind::InvalidDim.into());
+                    }
+                    Value::Real(list[1..].iter().try_fold(list[0], |a, &b| pick(a, b))?)
+                } else {
+                    zip_numbers(argument()?, argument()?, pick)?
+                }
+            }
+            // sum(, prod(, mean(, median(
+            Token::OneByte(0xB6 | 0xB7) => {
+                let list = list(argument()?)?;
+                let (start, end) = match count {
+                    1 => (0, list.len()),
+                    2 => (index(real(argument()?)?, list.len())?, list.len()),
+                    _ => (
+                        index(real(argument()?)?, list.len())?,
+                        index(real(argument()?)?, list.len())? + 1,
+                    ),
+                };
+                let items = list.get(start..end).unwrap_or_default();
+
+                Value::Real(if function == Token::OneByte(0xB6) {
+                    items.iter().try_fold(0.0, |total, x| checked(total + x))?
+                } else {
+                    items.iter().try_fold(1.0, |total, x| checked(total * x))?
+                })
+            }
+            Token::OneByte(0x21 | 0x1F) => {
+                let mut list = list(argument()?)?;
+                if list.is_empty() {
+                    return Err(ErrorKind::InvalidDim.into());
+                }
+
+                Value::Real(if function == Token::OneByte(0x21) {
+                    checked(list.iter().sum::<f64>() / list.len() as f64)?
+                } else {
+                    list.sort_by(|a, b| a.total_cmp(b));
+                    let middle = list.len() / 2;
+                    if list.len() % 2 == 0 {
+                        checked((list[middle - 1] + list[middle]) / 2.0)?
+                    } else {
+                        list[middle]
+                    }
+                })
+            }
+            // dim(, cumSum(, ΔList(, augment(
+            DIM => match argument()? {
+                Value::List(list) => Value::Real(list.len() as f64),
+                Value::Matrix(rows) => {
+                    Value::List(vec![rows.len() as f64, column_count(&rows) as f64])
+                }
+                _ => return Err(ErrorKind::DataType.into()),
+            },
+            Token::TwoByte(0xBB, 0x29) => {
+                let mut total = 0.0;
+                Value::List(
+                    list(argument()?)?
+                        .into_iter()
+                        .map(|x| {
+                            total = checked(total + x)?;
+                            Ok(total)
+                        })
+                        .collect::<Result<_, ErrorKind>>()?,
+                )
+            }
+            Token::TwoByte(0xBB, 0x2C) => {
+                let list = list(argument()?)?;
+                if list.len() < 2 {
+                    return Err(ErrorKind::InvalidDim.into());
+                }
+                Value::List(
+                    list.windows(2)
+                        .map(|pair| checked(pair[1] - pair[0]))
+                        .collect::<Result<_, _>>()?,
+                )
+            }
+            Token::OneByte(0x14) => {
+                let combined = [list(argument()?)?, list(argument()?)?].concat();
+                if combined.len() > MAX_LIST_LENGTH {
+                    return Err(ErrorKind::InvalidDim.into());
+                }
+                Value::List(combined)
+            }
+            // binompdf(, binomcdf(
+            Token::TwoByte(0xBB, 0x15 | 0x16) => {
+                let trials = integer(real(argument()?)?)?;
+                let probability = real(argument()?)?;
+                if trials < 0 || !(0.0..=1.0).contains(&probability) {
+                    return Err(ErrorKind::Domain.into());
+                }
+
+                let mut total = 0.0;
+                let mut distribution = (0..=trials)
+                    .map(|successes| {
+                        let chance = checked(
+                            combinations(trials as f64, successes as f64)?
+                                * probability.powi(successes as i32)
+                                * (1.0 - probability).powi((trials - successes) as i32),
+                        )?;
+
+                        if function == Token::TwoByte(0xBB, 0x16) {
+                            total = checked(total + chance)?;
+                            Ok(total)
+                        } else {
+                            Ok(chance)
+                        }
+                    })
+                    .collect::<Result<Vec<_>, ErrorKind>>()?;
+
+                if count > 2 {
+                    map_numbers(argument()?, |successes| {
+                        let successes = integer(successes)?;
+                        if successes < 0 {
+                            return Err(ErrorKind::Domain);
+                        }
+                        Ok(*distribution
+                            .get(successes as usize)
+                            .unwrap_or(distribution.last().unwrap()))
+                    })?
+                } else {
+                    // the cumulative distribution should end at exactly 1
+                    if function == Token::TwoByte(0xBB, 0x16) {
+                        *distribution.last_mut().unwrap() = 1.0;
+                    }
+                    Value::List(distribution)
+                }
+            }
+            // each key is pressed just long enough for one getKey to see it
+            GET_KEY => Value::Real(self.keys.pop_front().map_or(0.0, |key| key.code as f64)),
+            // rand, randInt(, randIntNoRep(
+            RAND => match count {
+                0 => Value::Real(self.random.next()),
+                _ => {
+                    let length = list_length(real(argument()?)?)?;
+                    Value::List((0..length).map(|_| self.random.next()).collect())
+                }
+            },
+            Token::TwoByte(0xBB, 0x0A) => {
+                let low = integer(real(argument()?)?)?;
+                let high = integer(real(argument()?)?)?;
+
+                if count > 2 {
+                    let length = list_length(real(argument()?)?)?;
+                    Value::List(
+                        (0..length)
+                            .map(|_| self.random.integer(low, high))
+                            .collect(),
+                    )
+                } else {
+                    Value::Real(self.random.integer(low, high))
+                }
+            }
+            Token::TwoByte(0xEF, 0x35) => {
+                let low = integer(real(argument()?)?)?;
+                let high = integer(real(argument()?)?)?;
+                let (low, high) = (low.min(high), low.max(high));
+                if high - low >= MAX_LIST_LENGTH as i64 {
+                    return Err(ErrorKind::InvalidDim.into());
+                }
+
+                let mut list = (low..=high).map(|x| x as f64).collect::<Vec<_>>();
+                for position in (1..list.len()).rev() {
+                    let other = self.random.integer(0, position as i64) as usize;
+                    list.swap(position, other);
+                }
+
+                if count > 2 {
+                    let length = list_length(real(argument()?)?)?;
+                    list.truncate(length);
+                }
+                Value::List(list)
+            }
+            // sub(, length(, inString(, expr(
+            Token::TwoByte(0xBB, 0x0C) => {
+                let text = string(argument()?)?;
+                let start = index(real(argument()?)?, text.len())?;
+                let length = integer(real(argument()?)?).map_err(|_| ErrorKind::Domain)?;
+                let end = start + length.max(0) as usize;
+                if length < 1 || end > text.len() {
+                    return Err(ErrorKind::Domain.into());
+                }
+
+                Value::Str(text[start..end].to_vec())
+            }
+            Token::TwoByte(0xBB, 0x2B) => Value::Real(string(argument()?)?.len() as f64),
+            Token::TwoByte(0xBB, 0x0F) => {
+                let text = string(argument()?)?;
+                let search = string(argument()?)?;
+                let start = if count > 2 {
+                    index(real(argument()?)?, text.len().max(1))?
+                } else {
+                    0
+                };
+
+                let position = (start..text.len())
+                    .find(|&position| text[position..].starts_with(&search))
+                    .map_or(0, |position| position + 1);
+
+                Value::Real(position as f64)
+            }
+            Token::TwoByte(0xBB, 0x2A) => {
+                let text = string(argument()?)?;
+                self.evaluate_tokens(&text)?
+            }
+            _ => return Err(Stop::Unsupported(token_text(function))),
+        };
+
+        if arguments.next().is_some() {
+            return Err(ErrorKind::Argument.into());
+        }
+
+        Ok(value)
+    }
+
+    /// `seq(expression,variable,start,end[,step])`, which leaves the variable as it was.
+    fn sequence(&mut self, arguments: &[Expr]) -> Result<Value, Stop> {
+        let (expression, variable, start, end, step) = match arguments {
+            [expression, Expr::Variable(variable), start, end] => {
+                (expression, variable, start, end, None)
+            }
+            [expression, Expr::Variable(variable), start, end, step] => {
+                (expression, variable, start, end, Some(step))
+            }
+            _ => return Err(ErrorKind::Argument.into()),
+        };
+        if kind(variable) != Kind::Real {
+            return Err(ErrorKind::Syntax.into());
+        }
+
+        let start = real(self.evaluate(start)?)?;
+        let end = real(self.evaluate(end)?)?;
+        let step = match step {
+            Some(step) => real(self.evaluate(step)?)?,
+            None => 1.0,
+        };
+        if step == 0.0 {
+            return Err(ErrorKind::Domain.into());
+        }
+
+        let previous = self.variables.get(variable).cloned();
+        let mut list = vec![];
+        let mut current = start;
+
+        let result = loop {
+            if (step > 0.0 && current > end) || (step < 0.0 && current < end) {
+                break Ok(Value::List(list));
+            }
+            if list.len() == MAX_LIST_LENGTH {
+                break Err(ErrorKind::InvalidDim.into());
+            }
+            if let Err(stop) = self.check_deadline() {
+                break Err(stop);
+            }
+
+            self.variables
+                .insert(variable.clone(), Value::Real(current));
+            match self.evaluate(expression).and_then(real) {
+                Ok(element) => list.push(element),
+                Err(stop) => break Err(stop),
+            }
+
+            current = match checked(current + step) {
+                Ok(next) => next,
+                Err(error) => break Err(error.into()),
+            };
+        };
+
+        match previous {
+            Some(value) => self.variables.insert(variable.clone(), value),
+            None => self.variables.remove(variable),
+        };
+
+        result
+    }
+}
+
+fn logarithm(x: f64, base: f64) -> Result<f64, ErrorKind> {
+    if x <= 0.0 {
+        return Err(if x == 0.0 {
+            ErrorKind::Domain
+        } else {
+            ErrorKind::Nonreal
+        });
+    }
+
+    checked(x.ln() / base.ln())
+}
+
+/// Runs each case in a fresh [Interpreter]. Used when there's no ROM to run CEmu with.
+pub struct InterpreterBackend;
+
+impl TestBackend for InterpreterBackend {
+    fn run_cases(
+        &mut self,
+        program: &Tokens,
+        cases: &[TestCase],
+        timeout: Duration,
+    ) -> Result<Vec<CaseOutcome>, TestError> {
+        let program = program.clone().collect::<Vec<_>>();
+
+        cases
+            .iter()
+            .map(|case| {
+                if case.graph.is_some() {
+                    return Err(TestError::Unsupported(
+                        "checking the graph screen".to_owned(),
+                    ));
+                }
+
+                // outputs found by running the starting program here are drawn the same way
+                let explicit = case.display.is_some()
+                    || case
+                        .outputs
+                        .iter()
+                        .any(|output| matches!(output, Output::Expected(_)));
+                if case.seed.is_some() && explicit {
+                    return Err(TestError::Unsupported(
+                        "checking seeded random numbers against fixed values".to_owned(),
+                    ));
+                }
+
+                let mut interpreter = Interpreter::new(Instant::now() + timeout);
+
+                let ran = case
+                    .inputs
+                    .iter()
+                    .try_for_each(|input| interpreter.set_variable(input))
+                    .and_then(|_| {
+                        if let Some(seed) = case.seed {
+                            interpreter.seed_random(seed as f64);
+                        }
+                        interpreter.press_keys(case.keys);
+
+                        interpreter.run(&program)
+                    });
+
+                // like on the calculator, whatever was stored before an error is kept
+                let ending = match ran {
+                    Err(Stop::TimedOut) => return Ok(CaseOutcome::TimedOut(timeout)),
+                    Err(Stop::Unsupported(what)) => return Err(TestError::Unsupported(what)),
+                    Err(Stop::Error(kind)) => Ending::Stopped(kind),
+                    Ok(()) => Ending::Completed,
+                };
+
+                let exports = case
+                    .outputs
+                    .iter()
+                    .filter_map(|output| {
+                        let value = interpreter.variable(output.name())?;
+                        if let Output::Expected(expected) = output {
+                            if value.file_extension() != expected.value.file_extension() {
+                                return None;
+                            }
+                        }
+
+                        let entry: TIEntry = Variable {
+                            name: output.name().to_owned(),
+                            value,
+                            comparison: Comparison::default(),
+                        }
+                        .into();
+
+                        Some((output.name().to_owned(), entry))
+                    })
+                    .collect();
+
+                let screen = match case.display {
+                    Some(display) => {
+                        let expected = HomeScreen::showing(display).map_err(TestError::Display)?;
+
+                        Some(if *interpreter.screen() == expected {
+                            ScreenCheck::Matches
+                        } else {
+                            ScreenCheck::Differs(Some(interpreter.screen().lines()))
+                        })
+                    }
+                    None => None,
+                };
+
+                Ok(CaseOutcome::Finished {
+                    exports,
+                    screen,
+                    graph: None,
+                    ending,
+                })
+            })
+            .collect()
+    }
+
+    fn time_runs(
+        &mut self,
+        _program: &Tokens,
+        _speed: &Speed,
+        _timeout: Duration,
+    ) -> Result<Option<f64>, TestError> {
+        Err(TestError::Unsupported("timing programs".to_owned()))
+    }
+
+    fn checks_graphs(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lesson::Test, parser::parse_lessons};
+
+    fn run(program: &str) -> (Result<(), Stop>, Interpreter) {
+        let mut interpreter = Interpreter::new(Instant::now() + Duration::from_secs(5));
+        let result = interpreter.run(&tokenize(program).collect::<Vec<_>>());
+
+        (result, interpreter)
+    }
+
+    #[test]
+    fn evaluates_expressions() {
+        let cases = [
+            ("1+2*3", "7"),
+            ("~2^2", "-4"),
+            ("2^3^2", "64"),
+            ("3(4+5", "27"),
+            ("2->A:5A^^2", "20"),
+            ("fPart(.1*30", "0"),
+            ("1=1 and 0 or 1", "1"),
+            ("not(0)+not(5", "1"),
+            ("{1,2,3}+{4,5,6", "{5,7,9}"),
+            ("2{1,2,3}^^2", "{2,8,18}"),
+            ("0rand(3", "{0,0,0}"),
+            ("seq(3X^^3,X,0,3", "{0,3,24,81}"),
+            ("cumSum(binomcdf(4,0", "{1,2,3,4,5}"),
+            ("binompdf(2,.5", "{0.25,0.5,0.25}"),
+            ("sum({1,2,3,4},2,3", "5"),
+            ("max({3,9,2})+min(4,7", "13"),
+            ("remainder(17,5", "2"),
+            ("iPart(~2.5)+int(~2.5", "-5"),
+            ("dim({4,5,6", "3"),
+            ("{RED,ORANGE,DARKGRAY", "{11,15,24}"),
+            ("\"AB\"+\"CD", "\"ABCD\""),
+            ("length(sub(\"HELLO\",2,3", "3"),
+            ("inString(\"HELLO\",\"L\"", "3"),
+            ("logBASE(64,8", "2"),
+            ("5 nCr 2+3!", "16"),
+            ("{1,2->L1:4->L1(3):L1", "{1,2,4}"),
+            ("3->dim(|LABC:|LABC", "{0,0,0}"),
+            ("[[1,2][3,4]]*[[1][1]]", "[[3][7]]"),
+            ("{2,3->dim([A]:dim([A]", "{2,3}"),
+        ];
+
+        for (program, expected) in cases {
+            let (result, interpreter) = run(program);
+
+            assert_eq!(result, Ok(()), "running {}", program);
+            assert_eq!(
+                interpreter.variable("Ans").unwrap().to_string(),
+                expected,
+                "running {}",
+                program
+            );
+        }
+    }
+
+    #[test]
+    fn runs_control_flow() {
+        let cases = [
+            ("0:For(I,1,10:Ans+I:End", "55"),
+            ("0:For(I,10,1,~2:Ans+I:End", "30"),
+            ("0:For(I,5,1:Ans+1:End", "0"),
+            ("0->A:While A<5:A+1->A:End:A", "5"),
+            ("0->A:Repeat A>=5:A+1->A:End:A", "5"),
+            ("If 0:1:2", "2"),
+            ("3:If 1:Then:4:Else:5:End", "4"),
+            ("3:If 0:Then:4:Else:5:End", "5"),
+            ("3:If 0:Then:While 1:End:End", "3"),
+            ("1:Goto A:2:Lbl A", "1"),
+            ("\"A:B\"", "\"A:B\""),
+            ("1:Return:2", "1"),
+        ];
+
+        for (program, expected) in cases {
+            let (result, interpreter) = run(program);
+
+            assert_eq!(result, Ok(()), "running {}", program);
+            assert_eq!(
+                interpreter.variable("Ans").unwrap().to_string(),
+                expected,
+                "running {}",
+                program
+            );
+        }
+    }
+
+    #[test]
+    fn stops_on_errors() {
+        let cases = [
+            ("1/0", Stop::Error(ErrorKind::DivideBy0)),
+            ("{1,2}+{1,2,3", Stop::Error(ErrorKind::DimMismatch)),
+            ("L1(1", Stop::Error(ErrorKind::Undefined)),
+            ("{1->A", Stop::Error(ErrorKind::DataType)),
+            ("1+", Stop::Error(ErrorKind::Syntax)),
+            ("Goto B", Stop::Error(ErrorKind::Label)),
+            ("{0,0->dim([A]", Stop::Error(ErrorKind::InvalidDim)),
+            ("{2,0->dim([A]", Stop::Error(ErrorKind::InvalidDim)),
+            ("{100,1->dim([A]", Stop::Error(ErrorKind::InvalidDim)),
+            ("[[1,2][3]]", Stop::Error(ErrorKind::InvalidDim)),
+            ("[[1][2]]+[[1,2]]", Stop::Error(ErrorKind::DimMismatch)),
+            ("[[1,2]]*[[1,2]]", Stop::Error(ErrorKind::DimMismatch)),
+            ("ZStandard", Stop::Unsupported("ZStandard".to_owned())),
+            ("While 1:End", Stop::TimedOut),
+        ];
+
+        for (program, expected) in cases {
+            let mut interpreter = Interpreter::new(Instant::now() + Duration::from_millis(50));
+            let result = interpreter.run(&tokenize(program).collect::<Vec<_>>());
+
+            assert_eq!(result, Err(expected), "running {}", program);
+        }
+    }
+
+    #[test]
+    fn rejects_misshapen_matrix_inputs() {
+        for rows in [vec![], vec![vec![]], vec![vec![1.0, 2.0], vec![3.0]]] {
+            let mut interpreter = Interpreter::new(Instant::now() + Duration::from_secs(5));
+            let input = Variable {
+                name: "[A]".to_owned(),
+                value: VariableData::RealMatrix(rows.clone()),
+                comparison: Comparison::default(),
+            };
+
+            assert_eq!(
+                interpreter.set_variable(&input),
+                Err(Stop::Error(ErrorKind::InvalidDim)),
+                "storing {:?}",
+                rows
+            );
+        }
+    }
+
+    #[test]
+    fn displays_on_the_home_screen() {
+        let (result, interpreter) =
+            run("Disp 1\nClrHome\nDisp 1/3,\"X<=theta\",{~2,.0001\nPause 12");
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(
+            interpreter.screen().lines(),
+            [
+                "               .3333333333",
+                "X<=theta",
+                "                 {~2 1|E~4}",
+                "                        12",
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_pressed_keys() {
+        let keys = ["1", "2", "enter", "chs", "3", "enter", "up"]
+            .map(|name| crate::keypad::find_key(name).unwrap());
+
+        let mut interpreter = Interpreter::new(Instant::now() + Duration::from_secs(5));
+        interpreter.press_keys(&keys);
+        let program = "Input \"N=\",N\nPrompt A\nRepeat Ans:getKey:End\nAns+N+A";
+        let result = interpreter.run(&tokenize(program).collect::<Vec<_>>());
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(interpreter.variable("Ans").unwrap().to_string(), "34");
+        assert_eq!(interpreter.screen().lines(), ["N=12", "A=?~3"]);
+
+        let (result, _) = run("Input A");
+        assert_eq!(result, Err(Stop::TimedOut));
+    }
+
+    #[test]
+    fn finds_variable_names() {
+        let program = tokenize("Input \"AB\",A\n{1,2->L1:\"CD->Str1\nAns+|LABC(1)->theta")
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            variable_names(&program).into_iter().collect::<Vec<_>>(),
+            ["A", "Ans", "L1", "Str1", "theta", "|LABC"]
+        );
+    }
+
+    /// Every lesson's starting program should pass the lesson's own behavior tests.
+    #[test]
+    fn starting_programs_pass_their_lessons() {
+        fn cases(test: &Test) -> Vec<TestCase<'_>> {
+            match test {
+                Test::Group(group) => group.iter().flat_map(cases).collect(),
+                test => TestCase::of(test),
+            }
+        }
+
+        for (id, (_, lesson)) in parse_lessons(None) {
+            let program = tokenize(&lesson.starting_program).collect::<Vec<_>>();
+
+            // the interpreter can't draw
+            for case in lesson
+                .tests
+                .iter()
+                .flat_map(cases)
+                .filter(|case| case.graph.is_none())
+            {
+                let mut interpreter = Interpreter::new(Instant::now() + Duration::from_secs(5));
+                for input in case.inputs {
+                    interpreter.set_variable(input).unwrap();
+                }
+                if let Some(seed) = case.seed {
+                    interpreter.seed_random(seed as f64);
+                }
+                interpreter.press_keys(case.keys);
+
+                assert_eq!(interpreter.run(&program), Ok(()), "lesson {}", id);
+                for output in case.outputs {
+                    let actual = interpreter.variable(output.name());
+
+                    match output {
+                        Output::Expected(expected) => assert_eq!(
+                            actual.map(|value| value.to_string()),
+                            Some(expected.value.to_string()),
+                            "lesson {}, output {}",
+                            id,
+                            expected.name
+                        ),
+                        // the expected value is whatever the starting program leaves behind
+                        Output::Generated(name) => {
+                            assert!(actual.is_some(), "lesson {}, output {}", id, name)
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
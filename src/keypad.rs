@@ -0,0 +1,88 @@
+//! The calculator's keys, which CEmu tests can press while the program runs.
+
+use std::fmt::Display;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Key {
+    /// The autotester's name for the key, which lessons use too.
+    pub name: &'static str,
+    /// What `getKey` returns while the key is pressed.
+    pub code: u8,
+    /// What the key types at an `Input` or `Prompt`, in accessible token names, if anything.
+    pub typed: Option<&'static str>,
+}
+
+impl Key {
+    pub fn is_enter(&self) -> bool {
+        self.code == ENTER_CODE
+    }
+}
+
+impl Display for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name)
+    }
+}
+
+const fn key(name: &'static str, code: u8, typed: Option<&'static str>) -> Key {
+    Key { name, code, typed }
+}
+
+/// Every key but ON, which breaks out of programs.
+pub const KEYS: [Key; 49] = [
+    key("yequ", 11, None),
+    key("window", 12, None),
+    key("zoom", 13, None),
+    key("trace", 14, None),
+    key("graph", 15, None),
+    key("2nd", 21, None),
+    key("mode", 22, None),
+    key("del", 23, None),
+    key("left", 24, None),
+    key("up", 25, None),
+    key("right", 26, None),
+    key("alpha", 31, None),
+    key("xton", 32, Some("X")),
+    key("stat", 33, None),
+    key("down", 34, None),
+    key("math", 41, None),
+    key("apps", 42, None),
+    key("prgm", 43, None),
+    key("vars", 44, None),
+    key("clear", 45, None),
+    key("inv", 51, Some("^^-1")),
+    key("sin", 52, Some("sin(")),
+    key("cos", 53, Some("cos(")),
+    key("tan", 54, Some("tan(")),
+    key("power", 55, Some("^")),
+    key("sq", 61, Some("^^2")),
+    key("comma", 62, Some(",")),
+    key("lpar", 63, Some("(")),
+    key("rpar", 64, Some(")")),
+    key("div", 65, Some("/")),
+    key("log", 71, Some("log(")),
+    key("7", 72, Some("7")),
+    key("8", 73, Some("8")),
+    key("9", 74, Some("9")),
+    key("mul", 75, Some("*")),
+    key("ln", 81, Some("ln(")),
+    key("4", 82, Some("4")),
+    key("5", 83, Some("5")),
+    key("6", 84, Some("6")),
+    key("sub", 85, Some("-")),
+    key("sto", 91, Some("->")),
+    key("1", 92, Some("1")),
+    key("2", 93, Some("2")),
+    key("3", 94, Some("3")),
+    key("add", 95, Some("+")),
+    key("0", 102, Some("0")),
+    key("dot", 103, Some(".")),
+    key("chs", 104, Some("~")),
+    key("enter", ENTER_CODE, None),
+];
+
+const ENTER_CODE: u8 = 105;
+
+pub fn find_key(name: &str) -> Option<&'static Key> {
+    KEYS.iter().find(|key| key.name == name)
+}
@@ -1,44 +1,528 @@
-use std::collections::BTreeSet;
+use std::{collections::BTreeSet, fmt::Display};
 
-use serde::Deserialize;
+use serde::{de::Error, Deserialize, Deserializer};
 
-use crate::tools::{byte_count, tokenize};
+use tifloats::Float;
+
+use crate::{
+    interpreter::ErrorKind,
+    keypad::{find_key, Key},
+    tools::{
+        byte_count, checked_float_to_tifloat, displayed_digits, text_to_tifloat, tifloat_to_float,
+        tokenize, tokenizer,
+    },
+};
+
+/// Accessible name of the `ʟ` token which starts custom list names.
+pub const CUSTOM_LIST_PREFIX: &str = "|L";
+
+const MAX_CUSTOM_LIST_NAME_LENGTH: usize = 5;
+/// The most rows or columns the calculator allows a matrix.
+pub const MAX_MATRIX_DIMENSION: usize = 99;
 
 #[derive(Deserialize, Debug)]
 pub enum Test {
     #[serde(untagged)]
     CEmu {
         input: Vec<Variable>,
-        output: Vec<Variable>,
+        #[serde(default)]
+        output: Vec<Output>,
+        /// The home screen the program should leave behind, from the top, if it's checked.
+        display: Option<Vec<DisplayLine>>,
+        /// The graph screen the program should leave behind, if it's checked. The graph is
+        /// cleared before the program runs.
+        graph: Option<Graph>,
+        /// Whether the program should stop with an error, if it's checked.
+        error: Option<ExpectedError>,
+        /// Pressed in order once the program is running, by the autotester's names for them,
+        /// eg. `["1", "2", "enter", "down"]`.
+        #[serde(default, deserialize_with = "deserialize_keys")]
+        keys: Vec<&'static Key>,
+        /// The test is run once for each seed, which is stored to `rand` before the program
+        /// runs, eg. `"seeds": [1, 2, 3]`.
+        #[serde(default)]
+        seeds: Vec<u64>,
     },
     #[serde(untagged)]
     FulltextMatch { regex: String },
+    /// Limits how many times the program uses a token, by its accessible name, eg.
+    /// `{"token": "max(", "at_least": 1}` or `{"token": "If ", "at_most": 0}`.
+    #[serde(untagged)]
+    TokenCount {
+        #[serde(deserialize_with = "deserialize_token")]
+        token: String,
+        at_least: Option<usize>,
+        at_most: Option<usize>,
+    },
+    /// Cases with random inputs, which pass if the program leaves the same outputs behind as
+    /// the lesson's starting program.
+    #[serde(untagged)]
+    Random {
+        random: Vec<RandomInput>,
+        #[serde(deserialize_with = "deserialize_variable_names")]
+        output: Vec<String>,
+        #[serde(default = "default_random_cases")]
+        cases: usize,
+        /// Makes the cases the same every time. Otherwise, the seed used is reported when a
+        /// case fails.
+        seed: Option<u64>,
+    },
     #[serde(untagged)]
     Group(Vec<Test>),
 }
 
+fn deserialize_keys<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<&'static Key>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|name| find_key(name).ok_or_else(|| D::Error::custom(format!("unknown key {}", name))))
+        .collect()
+}
+
+fn default_random_cases() -> usize {
+    20
+}
+
+/// An input of [Test::Random], eg. `{"name": "A", "integer": [1, 9]}`.
+#[derive(Deserialize, Debug)]
+pub struct RandomInput {
+    #[serde(deserialize_with = "deserialize_variable_name")]
+    pub name: String,
+    #[serde(flatten)]
+    pub domain: Domain,
+}
+
+/// The values a random input is chosen from.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Domain {
+    /// Integers between the bounds, inclusive, eg. `{"integer": [1, 9]}`.
+    Integer(i64, i64),
+    /// eg. `{"one_of": [1, 5, "HELLO"]}`
+    OneOf(Vec<VariableData>),
+    /// A real list with a length between the bounds, inclusive, eg.
+    /// `{"list": {"length": [1, 10], "elements": {"integer": [0, 99]}}}`.
+    List {
+        length: (usize, usize),
+        #[serde(deserialize_with = "deserialize_list_elements")]
+        elements: Box<Domain>,
+    },
+}
+
+impl Domain {
+    pub fn sample(&self, rng: &mut fastrand::Rng) -> VariableData {
+        match self {
+            Domain::Integer(low, high) => VariableData::RealNumber(rng.i64(*low..=*high) as f64),
+            Domain::OneOf(values) => values[rng.usize(..values.len())].clone(),
+            Domain::List { length, elements } => VariableData::RealList(
+                (0..rng.usize(length.0..=length.1))
+                    .map(|_| match elements.sample(rng) {
+                        VariableData::RealNumber(number) => number,
+                        _ => unreachable!("list elements are checked to be real numbers"),
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Problems with the domain, which would otherwise stop values being chosen from it.
+    pub fn problems(&self) -> Option<String> {
+        match self {
+            Domain::Integer(low, high) if low > high => {
+                Some(format!("The range [{}, {}] is empty.", low, high))
+            }
+            Domain::OneOf(values) if values.is_empty() => {
+                Some("one_of needs at least one value.".to_owned())
+            }
+            Domain::List { length, .. } if length.0 > length.1 => Some(format!(
+                "The list length range [{}, {}] is empty.",
+                length.0, length.1
+            )),
+            Domain::List { elements, .. } => elements.problems(),
+            _ => None,
+        }
+    }
+}
+
+fn deserialize_list_elements<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Box<Domain>, D::Error> {
+    let elements = Domain::deserialize(deserializer)?;
+
+    let is_real = match &elements {
+        Domain::Integer(..) => true,
+        Domain::OneOf(values) => values
+            .iter()
+            .all(|value| matches!(value, VariableData::RealNumber(_))),
+        Domain::List { .. } => false,
+    };
+
+    if !is_real {
+        return Err(D::Error::custom("list elements must be real numbers"));
+    }
+
+    Ok(Box::new(elements))
+}
+
 #[derive(Deserialize, Debug, Clone)]
 #[serde(untagged)]
 pub enum VariableData {
     String(String),
+    /// Tokenized like a string, eg. `{"equation": "X^^2"}` for `{Y1}`.
+    Equation {
+        equation: String,
+    },
+    /// Rows of a matrix, e.g. `[[1,2],[3,4]]`
+    #[serde(deserialize_with = "deserialize_matrix")]
+    RealMatrix(Vec<Vec<f64>>),
+    #[serde(deserialize_with = "deserialize_reals")]
     RealList(Vec<f64>),
+    ComplexList(Vec<Complex>),
     RealNumber(f64),
+    ComplexNumber(Complex),
+}
+
+fn deserialize_matrix<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<Vec<f64>>, D::Error> {
+    let rows = Vec::<Vec<Real>>::deserialize(deserializer)?
+        .into_iter()
+        .map(|row| {
+            row.into_iter()
+                .map(|Real(number)| number)
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let columns = rows.first().map_or(0, Vec::len);
+
+    let dimensions = 1..=MAX_MATRIX_DIMENSION;
+    if !dimensions.contains(&rows.len()) || !dimensions.contains(&columns) {
+        return Err(D::Error::custom(format!(
+            "a matrix must have between 1 and {} rows and columns",
+            MAX_MATRIX_DIMENSION
+        )));
+    }
+
+    if rows.iter().any(|row| row.len() != columns) {
+        return Err(D::Error::custom(
+            "every row of a matrix must be the same length",
+        ));
+    }
+
+    Ok(rows)
+}
+
+/// A real number, written either as a JSON number or as text, eg. `"0.1"`. Text is read digit by
+/// digit, so digits which an `f64` can't hold still count when it's rounded to the calculator's
+/// 14 significant figures. The rounded number is then held exactly. Either way, numbers outside
+/// the calculator's range are rejected.
+fn deserialize_real<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Literal {
+        Number(f64),
+        Text(String),
+    }
+
+    match Literal::deserialize(deserializer)? {
+        Literal::Number(number) => checked_float_to_tifloat(number)
+            .map(|_| number)
+            .map_err(D::Error::custom),
+        Literal::Text(text) => real_from_text(&text).map_err(D::Error::custom),
+    }
+}
+
+fn real_from_text(text: &str) -> Result<f64, String> {
+    Ok(tifloat_to_float(&text_to_tifloat(text)?))
+}
+
+#[derive(Deserialize)]
+struct Real(#[serde(deserialize_with = "deserialize_real")] f64);
+
+fn deserialize_reals<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<f64>, D::Error> {
+    Ok(Vec::<Real>::deserialize(deserializer)?
+        .into_iter()
+        .map(|Real(number)| number)
+        .collect())
+}
+
+/// Written as `{"re": 1, "im": 2}` in lesson metadata.
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct Complex {
+    #[serde(deserialize_with = "deserialize_real")]
+    pub re: f64,
+    #[serde(deserialize_with = "deserialize_real")]
+    pub im: f64,
 }
 
 impl VariableData {
     pub fn file_extension(&self) -> &str {
         match self {
             VariableData::String(_) => "8xs",
+            VariableData::Equation { .. } => "8xy",
+            VariableData::RealMatrix(_) => "8xm",
             VariableData::RealList(_) => "8xl",
+            VariableData::ComplexList(_) => "8xl",
             VariableData::RealNumber(_) => "8xn",
+            VariableData::ComplexNumber(_) => "8xc",
+        }
+    }
+}
+
+impl Display for VariableData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn join<T: Display>(items: &[T]) -> String {
+            items
+                .iter()
+                .map(|item| item.to_string())
+                .collect::<Vec<_>>()
+                .join(",")
+        }
+
+        match self {
+            VariableData::String(text) => write!(f, "\"{}\"", text),
+            VariableData::Equation { equation } => write!(f, "\"{}\"", equation),
+            VariableData::RealMatrix(rows) => {
+                f.write_str("[")?;
+                for row in rows {
+                    write!(f, "[{}]", join(row))?;
+                }
+                f.write_str("]")
+            }
+            VariableData::RealList(list) => write!(f, "{{{}}}", join(list)),
+            VariableData::ComplexList(list) => write!(f, "{{{}}}", join(list)),
+            VariableData::RealNumber(number) => write!(f, "{}", number),
+            VariableData::ComplexNumber(number) => write!(f, "{}", number),
+        }
+    }
+}
+
+impl Display for Complex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.im < 0.0 {
+            write!(f, "{}-{}i", self.re, -self.im)
+        } else {
+            write!(f, "{}+{}i", self.re, self.im)
         }
     }
 }
 
+/// A real variable's value can be written as text, eg. `{"name": "A", "value": "0.1"}`, since
+/// it can't hold a string.
 #[derive(Deserialize, Debug, Clone)]
+#[serde(try_from = "VariableFields")]
 pub struct Variable {
     pub name: String,
     pub value: VariableData,
+    pub comparison: Comparison,
+}
+
+#[derive(Deserialize)]
+struct VariableFields {
+    #[serde(deserialize_with = "deserialize_variable_name")]
+    name: String,
+    value: VariableData,
+    #[serde(default)]
+    comparison: Comparison,
+}
+
+impl TryFrom<VariableFields> for Variable {
+    type Error = String;
+
+    fn try_from(fields: VariableFields) -> Result<Self, Self::Error> {
+        let value = match fields.value {
+            VariableData::String(text) if is_real_variable_name(&fields.name) => {
+                VariableData::RealNumber(real_from_text(&text)?)
+            }
+            // caught here rather than in the untagged enum, so that the error says why
+            VariableData::RealNumber(number) => {
+                checked_float_to_tifloat(number)?;
+                VariableData::RealNumber(number)
+            }
+            value => value,
+        };
+
+        Ok(Variable {
+            name: fields.name,
+            value,
+            comparison: fields.comparison,
+        })
+    }
+}
+
+/// An output of a CEmu test. An output written as just a name, eg. `"Ans"`, is expected to
+/// match whatever the lesson's starting program leaves behind for the same inputs.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Output {
+    Expected(Variable),
+    #[serde(deserialize_with = "deserialize_variable_name")]
+    Generated(String),
+}
+
+impl Output {
+    pub fn name(&self) -> &str {
+        match self {
+            Output::Expected(variable) => &variable.name,
+            Output::Generated(name) => name,
+        }
+    }
+}
+
+/// eg. `"error": "DIVIDE BY 0"`, or `"error": "none"` for a program which should finish
+/// without an error.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpectedError {
+    #[serde(rename = "none")]
+    Nothing,
+    #[serde(untagged)]
+    Error(ErrorKind),
+}
+
+/// How a CEmu test checks the graph screen.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Graph {
+    /// CRC-32s of the LCD below the status bar, any of which passes, eg.
+    /// `{"crcs": ["1A2B3C4D"]}`. CEmu's autotester dock can find them. Such a test can't check
+    /// the home screen or an error as well.
+    Crcs(Vec<String>),
+    /// What this reference program draws for the same inputs, eg. `{"like": "Line(0,0,1,1"}`.
+    Like(String),
+}
+
+/// A line of the home screen, eg. `"display": [5, "FIZZ"]`. Numbers are right-aligned and
+/// text is left-aligned, as `Disp` shows them. Text is written with accessible token names.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum DisplayLine {
+    Number(f64),
+    Text(String),
+}
+
+impl Display for DisplayLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DisplayLine::Number(number) => write!(f, "{}", number),
+            DisplayLine::Text(text) => write!(f, "\"{}\"", text),
+        }
+    }
+}
+
+/// How the numbers in an output variable are compared against what the program produced.
+/// Strings and equations are always compared exactly.
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Comparison {
+    /// Byte-for-byte, including the calculator's guard digits.
+    #[default]
+    Exact,
+    /// Equal when rounded to the 10 digits shown on the home screen.
+    Displayed,
+    /// Within a fixed distance of the expected value, eg. `{"absolute": 1e-9}`.
+    Absolute(f64),
+    /// Within a fraction of the expected value, eg. `{"relative": 1e-9}`.
+    Relative(f64),
+}
+
+impl Comparison {
+    pub fn floats_match(&self, expected: &Float, actual: &Float) -> bool {
+        match self {
+            Comparison::Exact => expected == actual,
+            Comparison::Displayed => displayed_digits(expected) == displayed_digits(actual),
+            Comparison::Absolute(epsilon) => {
+                (tifloat_to_float(expected) - tifloat_to_float(actual)).abs() <= *epsilon
+            }
+            Comparison::Relative(epsilon) => {
+                let expected = tifloat_to_float(expected);
+
+                (expected - tifloat_to_float(actual)).abs() <= epsilon * expected.abs()
+            }
+        }
+    }
+}
+
+fn is_real_variable_name(name: &str) -> bool {
+    name == "theta" || matches!(name.as_bytes(), [b'A'..=b'Z'])
+}
+
+fn is_equation_name(name: &str) -> bool {
+    match name.as_bytes() {
+        [b'Y' | b'r', digit] => digit.is_ascii_digit(),
+        [b'X' | b'Y', digit, b'T'] => digit.is_ascii_digit(),
+        _ => false,
+    }
+}
+
+impl Variable {
+    /// The name of a custom list without its `ʟ` prefix, eg. `SCORE` for `ʟSCORE`.
+    pub fn custom_list_name(&self) -> Option<&str> {
+        self.name.strip_prefix(CUSTOM_LIST_PREFIX)
+    }
+}
+
+/// Accepts both `ʟSCORE` and `|LSCORE`, normalizing to the latter so the name can be tokenized.
+///
+/// Equation variables may be written without braces (`Y1` rather than `{Y1}`).
+/// A token's accessible name, which must tokenize to exactly that one token.
+fn deserialize_token<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let token = String::deserialize(deserializer)?;
+
+    match tokenizer().tokenize(&token) {
+        Ok((tokens, _)) if tokens.clone().count() == 1 => Ok(token),
+        _ => Err(D::Error::custom(format!("{} is not a single token", token))),
+    }
+}
+
+fn deserialize_variable_name<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<String, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    let name = match name.strip_prefix('\u{029F}') {
+        Some(list_name) => CUSTOM_LIST_PREFIX.to_owned() + list_name,
+        None if is_equation_name(&name) => format!("{{{}}}", name),
+        None => name,
+    };
+
+    if let Some(list_name) = name.strip_prefix(CUSTOM_LIST_PREFIX) {
+        let tokens = tokenizer()
+            .tokenize(list_name)
+            .map_err(|_| D::Error::custom(format!("invalid custom list name {}", name)))?
+            .0
+            .collect::<Vec<_>>();
+
+        if tokens.is_empty()
+            || !tokens[0].is_alpha()
+            || !tokens.iter().all(|token| token.is_alphanumeric())
+        {
+            return Err(D::Error::custom(format!(
+                "custom list name {} must be a letter or theta followed by letters, numbers, or theta",
+                name
+            )));
+        }
+
+        if tokens.len() > MAX_CUSTOM_LIST_NAME_LENGTH {
+            return Err(D::Error::custom(format!(
+                "custom list name {} is longer than {} characters",
+                name, MAX_CUSTOM_LIST_NAME_LENGTH
+            )));
+        }
+    }
+
+    Ok(name)
+}
+
+fn deserialize_variable_names<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    struct Name(#[serde(deserialize_with = "deserialize_variable_name")] String);
+
+    Ok(Vec::<Name>::deserialize(deserializer)?
+        .into_iter()
+        .map(|Name(name)| name)
+        .collect())
 }
 
 #[derive(Deserialize)]
@@ -50,6 +534,29 @@ pub struct Lesson {
     pub required_savings: usize,
     pub brief_description: Option<String>,
     pub tests: Vec<Test>,
+    /// Seconds a program may run for in each case, overriding the global timeout.
+    pub timeout: Option<u64>,
+    /// The author's own solutions, checked by `basiclings check-lessons --verify`.
+    #[serde(default)]
+    pub reference_solutions: Vec<String>,
+    /// Times submissions which pass the tests, to show alongside their size.
+    pub speed: Option<Speed>,
+}
+
+/// A lesson's speed test, eg. `{"input": [...], "runs": 20, "seconds": 10, "no_slower": true}`.
+///
+/// The program is timed on the calculator, with its own clock. That only counts whole seconds,
+/// so there should be enough runs to take a few.
+#[derive(Deserialize, Debug)]
+pub struct Speed {
+    /// Set before each run.
+    pub input: Vec<Variable>,
+    pub runs: u32,
+    /// How long the runs may take altogether. A program which takes longer fails.
+    pub seconds: u32,
+    /// Whether the program must also be no slower than the starting program.
+    #[serde(default)]
+    pub no_slower: bool,
 }
 
 impl Lesson {
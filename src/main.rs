@@ -1,13 +1,42 @@
+use std::{env, process::ExitCode};
+
+use args::{Arguments, Command};
 use cli::UserInterface;
 
+mod args;
 mod cemu;
+mod check;
 mod cli;
+mod config;
+mod interpreter;
+mod keypad;
 mod lesson;
 mod parser;
+mod screen;
 mod tools;
 
-fn main() {
-    let mut cli = UserInterface::new().unwrap();
+fn main() -> ExitCode {
+    match args::parse(env::args().skip(1)) {
+        Ok(Arguments {
+            command: Command::Interactive,
+            lessons_dir,
+        }) => {
+            let mut cli = UserInterface::new(lessons_dir).unwrap();
 
-    cli.run()
+            cli.run();
+            ExitCode::SUCCESS
+        }
+        Ok(Arguments {
+            command: Command::Test(options),
+            lessons_dir,
+        }) => cli::run_test_command(&options, lessons_dir),
+        Ok(Arguments {
+            command: Command::CheckLessons { verify },
+            lessons_dir,
+        }) => check::run_check_command(lessons_dir, verify),
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, args::USAGE);
+            ExitCode::from(cli::EXIT_ERROR)
+        }
+    }
 }
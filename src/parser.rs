@@ -1,4 +1,9 @@
-use std::collections::BTreeMap;
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    fs, io,
+    path::{Path, PathBuf},
+};
 
 use markdown::{
     mdast::{Code, Node},
@@ -12,39 +17,153 @@ use crate::lesson::Lesson;
 #[folder = "lessons/"]
 pub struct LessonData;
 
-pub fn parse_lessons() -> BTreeMap<u16, (Node, Lesson)> {
-    let mut data = BTreeMap::new();
-
-    for file_path in LessonData::iter() {
-        // Every lesson should be valid UTF8 & markdown- I don't feel so bad making the runtime errors worse than useless.
-        let raw_data = LessonData::get(&file_path).unwrap().data;
-        let lesson_data = String::from_utf8(raw_data.to_vec()).expect("UTF-8 parsing error");
-        let ast =
-            markdown::to_mdast(&lesson_data, &ParseOptions::gfm()).expect("Markdown parsing error");
-
-        // extract the yaml metadata (usually at the end)
-        let metadata = ast.children().unwrap().iter().find_map(|node| match &node {
-            Node::Code(Code {
-                lang: Some(lang),
-                value,
-                ..
-            }) if lang == "json" => Some(value),
-            _ => None,
-        });
-
-        if metadata.is_none() {
-            eprintln!("Missing metadata for {}", file_path);
-            continue;
+pub enum LessonError {
+    Io(io::Error),
+    Encoding,
+    MissingMetadata,
+    Markdown(String),
+    Metadata(serde_json::Error),
+}
+
+impl Display for LessonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LessonError::Io(err) => write!(f, "{}", err),
+            LessonError::Encoding => write!(f, "UTF-8 parsing error"),
+            LessonError::MissingMetadata => write!(f, "Missing metadata"),
+            LessonError::Markdown(message) => write!(f, "Markdown parsing error: {}", message),
+            LessonError::Metadata(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+/// A lesson's Markdown file, and the lesson in it if it could be parsed.
+pub struct LessonFile {
+    pub path: String,
+    /// Whether the lesson is one of the `lessons/` embedded at compile time.
+    pub embedded: bool,
+    pub lesson: Result<(Node, Lesson), LessonError>,
+}
+
+/// Reads the embedded lessons, then any Markdown lessons in `lessons_dir` (searched
+/// recursively) in order of their paths.
+pub fn lesson_files(lessons_dir: Option<&Path>) -> Vec<LessonFile> {
+    let mut files: Vec<LessonFile> = LessonData::iter()
+        .map(|file_path| {
+            let raw_data = LessonData::get(&file_path).unwrap().data;
+
+            LessonFile {
+                path: format!("lessons/{}", file_path),
+                embedded: true,
+                lesson: String::from_utf8(raw_data.to_vec())
+                    .map_err(|_| LessonError::Encoding)
+                    .and_then(|lesson_data| parse_lesson(&lesson_data)),
+            }
+        })
+        .collect();
+
+    if let Some(lessons_dir) = lessons_dir {
+        let mut paths = Vec::new();
+        if let Err(err) = find_markdown_files(lessons_dir, &mut paths) {
+            files.push(LessonFile {
+                path: lessons_dir.display().to_string(),
+                embedded: false,
+                lesson: Err(LessonError::Io(err)),
+            });
         }
+        paths.sort();
+
+        files.extend(paths.into_iter().map(|path| {
+            LessonFile {
+                path: path.display().to_string(),
+                embedded: false,
+                lesson: fs::read_to_string(&path)
+                    .map_err(LessonError::Io)
+                    .and_then(|lesson_data| parse_lesson(&lesson_data)),
+            }
+        }));
+    }
+
+    files
+}
+
+/// Parses the embedded lessons, then any in `lessons_dir`, which replace embedded lessons with
+/// the same id. Lessons on disk are written by teachers, so mistakes in them are reported and
+/// skipped rather than stopping the program.
+pub fn parse_lessons(lessons_dir: Option<&Path>) -> BTreeMap<u16, (Node, Lesson)> {
+    let mut data: BTreeMap<u16, (Node, Lesson)> = BTreeMap::new();
+    let mut sources = BTreeMap::<u16, String>::new();
 
-        let lesson: Result<Lesson, serde_json::Error> = serde_json::from_str(metadata.unwrap());
-        match lesson {
-            Ok(lesson) => {
-                data.insert(lesson.id, (ast, lesson));
+    for file in lesson_files(lessons_dir) {
+        let (ast, lesson) = match file.lesson {
+            Ok(parsed) => parsed,
+            // Every embedded lesson should be valid UTF8 & markdown- I don't feel so bad making the runtime errors worse than useless.
+            Err(LessonError::MissingMetadata) if file.embedded => {
+                eprintln!("Missing metadata for {}", file.path);
+                continue;
             }
-            Err(err) => panic!("Error in lesson {}:\n{}", file_path, err),
+            Err(err) if file.embedded => panic!("Error in lesson {}:\n{}", file.path, err),
+            Err(err) => {
+                eprintln!("Skipping {}: {}", file.path, err);
+                continue;
+            }
+        };
+
+        if !file.embedded {
+            if let Some(first_path) = sources.get(&lesson.id) {
+                eprintln!(
+                    "Skipping {}: lesson {} is already defined by {}.",
+                    file.path, lesson.id, first_path
+                );
+                continue;
+            }
+
+            if let Some((_, replaced)) = data.get(&lesson.id) {
+                eprintln!(
+                    "Lesson {} (\"{}\") is replaced by {}.",
+                    lesson.id, replaced.name, file.path
+                );
+            }
+
+            sources.insert(lesson.id, file.path);
         }
+
+        data.insert(lesson.id, (ast, lesson));
     }
 
     data
 }
+
+fn find_markdown_files(dir: &Path, paths: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            find_markdown_files(&path, paths)?;
+        } else if path.extension().is_some_and(|extension| extension == "md") {
+            paths.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_lesson(lesson_data: &str) -> Result<(Node, Lesson), LessonError> {
+    let ast = markdown::to_mdast(lesson_data, &ParseOptions::gfm())
+        .map_err(|err| LessonError::Markdown(err.to_string()))?;
+
+    // extract the yaml metadata (usually at the end)
+    let metadata = ast.children().unwrap().iter().find_map(|node| match &node {
+        Node::Code(Code {
+            lang: Some(lang),
+            value,
+            ..
+        }) if lang == "json" => Some(value),
+        _ => None,
+    });
+
+    let lesson = serde_json::from_str(metadata.ok_or(LessonError::MissingMetadata)?)
+        .map_err(LessonError::Metadata)?;
+
+    Ok((ast, lesson))
+}
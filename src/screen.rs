@@ -0,0 +1,184 @@
+//! The home screen, kept as the calculator keeps it in `textShadow`: a grid of characters from
+//! its font. Tests compare what programs display against it.
+
+use crate::{
+    lesson::DisplayLine,
+    tools::{displayed_number, tokenizer},
+};
+
+pub const COLUMNS: usize = 26;
+pub const ROWS: usize = 10;
+/// Where `textShadow` is in the calculator's memory. It's [ROWS] * [COLUMNS] bytes long.
+pub const TEXT_SHADOW_ADDRESS: u32 = 0xD006C0;
+
+const SPACE: u8 = b' ';
+
+/// Characters of the calculator's font which aren't ASCII, by the accessible names of the
+/// tokens which are displayed with them.
+const SPECIAL_CHARACTERS: [(&str, &[u8]); 10] = [
+    ("sqrt(", &[0x10, b'(']),
+    ("^^-1", &[0x11]),
+    ("^^2", &[0x12]),
+    ("<=", &[0x17]),
+    ("!=", &[0x18]),
+    (">=", &[0x19]),
+    ("~", &[0x1A]),
+    ("|E", &[0x1B]),
+    ("->", &[0x1C]),
+    ("theta", &[0x5B]),
+];
+
+/// The characters a token with this accessible name is displayed as, if they're known.
+pub fn token_characters(name: &str) -> Option<Vec<u8>> {
+    if let Some((_, characters)) = SPECIAL_CHARACTERS
+        .iter()
+        .find(|(special_name, _)| *special_name == name)
+    {
+        return Some(characters.to_vec());
+    }
+
+    name.bytes()
+        .all(|byte| (0x20..0x7F).contains(&byte))
+        .then(|| name.as_bytes().to_vec())
+}
+
+/// Text written with accessible token names, eg. `~1.5|E12`, as the characters displayed.
+pub fn text_characters(text: &str) -> Result<Vec<u8>, String> {
+    let tokens = tokenizer()
+        .tokenize(text)
+        .map_err(|_| format!("{} does not tokenize", text))?
+        .0;
+
+    let mut characters = vec![];
+    for token in tokens {
+        let name = tokenizer().stringify(&[token]).to_string();
+        let token_characters =
+            token_characters(&name).ok_or_else(|| format!("{} can't be displayed", name))?;
+        characters.extend(token_characters);
+    }
+
+    Ok(characters)
+}
+
+fn character_text(character: u8) -> String {
+    match SPECIAL_CHARACTERS
+        .iter()
+        .find(|(_, characters)| *characters == [character])
+    {
+        Some((name, _)) => name.to_string(),
+        None => (character as char).to_string(),
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct HomeScreen {
+    rows: [[u8; COLUMNS]; ROWS],
+    /// The row the next line is displayed on, which is [ROWS] once the screen is full.
+    cursor_row: usize,
+}
+
+impl Default for HomeScreen {
+    fn default() -> Self {
+        HomeScreen {
+            rows: [[SPACE; COLUMNS]; ROWS],
+            cursor_row: 0,
+        }
+    }
+}
+
+impl HomeScreen {
+    /// The screen after `ClrHome` and then a `Disp` of each line.
+    pub fn showing(lines: &[DisplayLine]) -> Result<Self, String> {
+        let mut screen = HomeScreen::default();
+
+        for line in lines {
+            let displayed = match line {
+                DisplayLine::Number(number) => {
+                    screen.display(&text_characters(&displayed_number(*number))?, true)
+                }
+                DisplayLine::Text(text) => screen.display(&text_characters(text)?, false),
+            };
+
+            if !displayed {
+                return Err(format!("{} is longer than {} characters", line, COLUMNS));
+            }
+        }
+
+        Ok(screen)
+    }
+
+    pub fn clear(&mut self) {
+        *self = HomeScreen::default();
+    }
+
+    /// Shows a line as `Disp` does, scrolling the screen up once it's full. Numbers are
+    /// right-aligned, and text is left-aligned.
+    ///
+    /// Returns false if the line is too long to fit, which isn't supported.
+    pub fn display(&mut self, characters: &[u8], align_right: bool) -> bool {
+        if characters.len() > COLUMNS {
+            return false;
+        }
+
+        if self.cursor_row == ROWS {
+            self.rows.rotate_left(1);
+            self.cursor_row -= 1;
+        }
+
+        let row = &mut self.rows[self.cursor_row];
+        *row = [SPACE; COLUMNS];
+        let start = if align_right {
+            COLUMNS - characters.len()
+        } else {
+            0
+        };
+        row[start..start + characters.len()].copy_from_slice(characters);
+
+        self.cursor_row += 1;
+        true
+    }
+
+    /// The contents of `textShadow`.
+    pub fn text_shadow(&self) -> Vec<u8> {
+        self.rows.concat()
+    }
+
+    /// Each row as text, without trailing spaces or blank rows at the bottom.
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = self
+            .rows
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|&character| character_text(character))
+                    .collect::<String>()
+                    .trim_end()
+                    .to_owned()
+            })
+            .collect::<Vec<_>>();
+
+        while lines.last().is_some_and(|line| line.is_empty()) {
+            lines.pop();
+        }
+
+        lines
+    }
+}
+
+/// The CRC-32 which the autotester hashes memory with.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    !crc
+}
@@ -3,6 +3,11 @@ use std::sync::OnceLock;
 use tifloats::Float;
 use titokens::{Token, Tokenizer, Tokens, Version};
 
+const COMPLEX_HALF_FLAGS: u8 = 0x0C;
+const MANTISSA_DIGITS: usize = 14;
+const DISPLAYED_DIGITS: usize = 10;
+const MAX_EXPONENT: i32 = 99;
+
 pub fn tokenizer() -> &'static Tokenizer {
     static TOKENIZER: OnceLock<Tokenizer> = OnceLock::new();
 
@@ -23,35 +28,414 @@ pub fn byte_count(tokens: &[Token]) -> usize {
         .sum()
 }
 
-pub fn process_submission(submission: String) -> Tokens {
-    let filtered = submission
+/// Tokenizes a submission, or returns the first line which can't be tokenized.
+pub fn process_submission(submission: String) -> Result<Tokens, String> {
+    match tokenizer().tokenize(&strip_comments(&submission)) {
+        Ok((tokens, _boundaries)) => Ok(tokens),
+        Err(()) => Err(strip_comments(&submission)
+            .lines()
+            .find(|line| tokenizer().tokenize(line).is_err())
+            .unwrap_or_default()
+            .to_owned()),
+    }
+}
+
+/// Removes the blank lines and `//` comments which submissions may contain.
+pub fn strip_comments(submission: &str) -> String {
+    submission
         .lines()
         .filter(|&line| !(line.is_empty() || line.starts_with("//")))
         .collect::<Vec<_>>()
-        .join("\n");
-
-    let (tokens, _boundaries) = tokenizer().tokenize(&filtered).unwrap();
-    tokens
+        .join("\n")
 }
 
+/// Rust formats floats as the shortest decimal which round-trips, which recovers the number exactly
+/// as it was written in the lesson (`0.3` rather than `0.29999999999999998...`). Those digits are
+/// then rounded to 14 significant figures, half away from zero, as the calculator does.
+///
+/// Panics if the number is out of the calculator's range, which lessons' numbers are checked for
+/// when they're read, see [checked_float_to_tifloat].
 pub fn float_to_tifloat(value: f64) -> Float {
+    checked_float_to_tifloat(value).unwrap()
+}
+
+/// Like [float_to_tifloat], but numbers which round to 1|E100 or more, or are too small to write,
+/// are an error.
+pub fn checked_float_to_tifloat(value: f64) -> Result<Float, String> {
     if value == 0.0 {
-        return Float::new_unchecked(false, 0, 0);
+        return Ok(Float::new_unchecked(false, 0, 0));
     }
 
-    let exponent = value.abs().log10().floor() as i8;
+    let scientific = format!("{:e}", value.abs());
+    let (significand, exponent) = scientific.split_once('e').unwrap();
+    let digits = significand
+        .bytes()
+        .filter(u8::is_ascii_digit)
+        .map(|digit| digit - b'0')
+        .collect();
+
+    decimal_to_tifloat(value.is_sign_negative(), digits, exponent.parse().unwrap())
+}
+
+/// A decimal written as text, eg. `0.1`, `-2.5` or `1.5e-3`, as a TI float. The digits are
+/// read one at a time, so none are lost before they're rounded to 14 significant figures.
+pub fn text_to_tifloat(text: &str) -> Result<Float, String> {
+    let invalid = || format!("{} is not a decimal number", text);
+
+    let (negative, unsigned) = match text.strip_prefix('-') {
+        Some(unsigned) => (true, unsigned),
+        None => (false, text),
+    };
+    let (significand, exponent) = match unsigned.split_once(['e', 'E']) {
+        Some((significand, exponent)) => {
+            (significand, exponent.parse::<i32>().map_err(|_| invalid())?)
+        }
+        None => (unsigned, 0),
+    };
+    let (integer, fraction) = significand.split_once('.').unwrap_or((significand, ""));
 
-    let mut rescaled = (10.0f64).powi(-exponent as i32) * value.abs();
-    let mut digits = Vec::with_capacity(15);
-    for _ in 0..15 {
-        digits.push(rescaled.trunc() as u8);
-        rescaled = rescaled.fract() * 10.0;
+    let digits = integer.bytes().chain(fraction.bytes()).collect::<Vec<_>>();
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+        return Err(invalid());
     }
 
-    Float::new(
-        value.is_sign_negative(),
-        exponent,
-        Float::mantissa_from(&digits),
+    decimal_to_tifloat(
+        negative,
+        digits.iter().map(|digit| digit - b'0').collect(),
+        exponent + integer.len() as i32 - 1,
     )
-    .unwrap()
+}
+
+/// Rounds decimal digits to a TI float, where `exponent` is the power of ten of the first one.
+fn decimal_to_tifloat(negative: bool, mut digits: Vec<u8>, exponent: i32) -> Result<Float, String> {
+    let leading_zeros = digits.iter().take_while(|&&digit| digit == 0).count();
+    if leading_zeros == digits.len() {
+        return Ok(Float::new_unchecked(false, 0, 0));
+    }
+
+    digits.drain(..leading_zeros);
+    let mut exponent = exponent - leading_zeros as i32;
+    round_digits(&mut digits, &mut exponent, MANTISSA_DIGITS);
+
+    if !(-MAX_EXPONENT..=MAX_EXPONENT).contains(&exponent) {
+        return Err(format!(
+            "{}|E{} is out of the calculator's range",
+            digits[0], exponent
+        ));
+    }
+
+    Float::new(negative, exponent as i8, Float::mantissa_from(&digits))
+        .map_err(|err| format!("{:?}", err))
+}
+
+/// TI-BASIC for a number, eg. `~1.5|E~3`, with the same digits [float_to_tifloat] produces.
+pub fn float_literal(value: f64) -> String {
+    let float = float_to_tifloat(value);
+    let digits = float
+        .significant_figures()
+        .into_iter()
+        .map(|digit| (b'0' + digit) as char)
+        .collect::<String>();
+
+    let mut literal = if float.is_negative() {
+        "~".to_owned()
+    } else {
+        String::new()
+    };
+
+    literal += &digits[..1];
+    if digits.len() > 1 {
+        literal += ".";
+        literal += &digits[1..];
+    }
+
+    match float.exponent() {
+        0 => {}
+        exponent if exponent < 0 => literal += &format!("|E~{}", -exponent),
+        exponent => literal += &format!("|E{}", exponent),
+    }
+
+    literal
+}
+
+/// Round a decimal significand to `places` digits, half away from zero.
+fn round_digits(digits: &mut Vec<u8>, exponent: &mut i32, places: usize) {
+    if digits.len() <= places {
+        return;
+    }
+
+    let round_up = digits[places] >= 5;
+    digits.truncate(places);
+
+    if round_up {
+        match digits.iter().rposition(|&digit| digit != 9) {
+            Some(position) => {
+                digits[position] += 1;
+                digits.truncate(position + 1);
+            }
+            // 99999999999999.5 carries all the way to 1E14
+            None => {
+                *digits = vec![1];
+                *exponent += 1;
+            }
+        }
+    }
+}
+
+pub fn tifloat_to_float(value: &Float) -> f64 {
+    let digits = value
+        .digits()
+        .iter()
+        .map(|digit| (b'0' + digit) as char)
+        .collect::<String>();
+
+    let magnitude: f64 = format!("0.{}e{}", digits, value.exponent() as i32 + 1)
+        .parse()
+        .unwrap();
+
+    if value.is_negative() {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
+
+/// Sign, exponent, and significant digits of the number as the calculator would display it.
+pub fn displayed_digits(value: &Float) -> (bool, i32, Vec<u8>) {
+    let mut digits = value.significant_figures();
+    if digits == [0] {
+        return (false, 0, digits);
+    }
+
+    let mut exponent = value.exponent() as i32;
+    round_digits(&mut digits, &mut exponent, DISPLAYED_DIGITS);
+    digits.truncate(digits.iter().rposition(|&digit| digit != 0).unwrap() + 1);
+
+    (value.is_negative(), exponent, digits)
+}
+
+/// A real number as `Disp` shows it in Normal mode, written with accessible token names, eg.
+/// `~.0025` or `1.5|E12`.
+pub fn displayed_number(value: f64) -> String {
+    fn digit_text(digits: &[u8]) -> String {
+        digits.iter().map(|digit| (b'0' + digit) as char).collect()
+    }
+
+    let value = float_to_tifloat(value);
+    let (negative, mut exponent, mut digits) = displayed_digits(&value);
+    let sign = if negative { "~" } else { "" };
+
+    if !(-3..10).contains(&exponent) {
+        let fraction = match digits.len() {
+            1 => String::new(),
+            _ => ".".to_owned() + &digit_text(&digits[1..]),
+        };
+        let exponent_sign = if exponent < 0 { "~" } else { "" };
+
+        return format!(
+            "{}{}{}|E{}{}",
+            sign,
+            digits[0],
+            fraction,
+            exponent_sign,
+            exponent.abs()
+        );
+    }
+
+    if exponent < 0 {
+        // only 10 digits fit after the decimal point, including the leading zeros
+        digits = value.significant_figures();
+        exponent = value.exponent() as i32;
+        let leading_zeros = (-exponent - 1) as usize;
+        round_digits(&mut digits, &mut exponent, DISPLAYED_DIGITS - leading_zeros);
+        digits.truncate(digits.iter().rposition(|&digit| digit != 0).unwrap() + 1);
+
+        let leading_zeros = "0".repeat((-exponent - 1) as usize);
+        return format!("{}.{}{}", sign, leading_zeros, digit_text(&digits));
+    }
+
+    let integer_length = exponent as usize + 1;
+    if digits.len() <= integer_length {
+        digits.resize(integer_length, 0);
+        format!("{}{}", sign, digit_text(&digits))
+    } else {
+        format!(
+            "{}{}.{}",
+            sign,
+            digit_text(&digits[..integer_length]),
+            digit_text(&digits[integer_length..])
+        )
+    }
+}
+
+/// The real and imaginary halves of a complex number, in the order the calculator stores them.
+pub fn complex_to_raw_bytes(re: f64, im: f64) -> [u8; 18] {
+    let mut bytes = [0u8; 18];
+    bytes[..9].copy_from_slice(&float_to_tifloat(re).to_raw_bytes());
+    bytes[9..].copy_from_slice(&float_to_tifloat(im).to_raw_bytes());
+
+    // Float::mark_complex_half masks the flags rather than setting them, so do it by hand.
+    bytes[0] |= COMPLEX_HALF_FLAGS;
+    bytes[9] |= COMPLEX_HALF_FLAGS;
+
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn float_to_tifloat_edge_cases() {
+        let cases = [
+            (0.0, false, 0, 0x00000000000000),
+            (1.0, false, 0, 0x10000000000000),
+            (-2.5, true, 0, 0x25000000000000),
+            (0.3, false, -1, 0x30000000000000),
+            (0.1 + 0.2, false, -1, 0x30000000000000),
+            (1e-5, false, -5, 0x10000000000000),
+            (999.9999999, false, 2, 0x99999999990000),
+            (1.0 / 3.0, false, -1, 0x33333333333333),
+            (2.0 / 3.0, false, -1, 0x66666666666667),
+            (-2.0 / 3.0, true, -1, 0x66666666666667),
+            (123456789012345.0, false, 14, 0x12345678901235),
+            (99999999999999.5, false, 14, 0x10000000000000),
+            (21380977.0, false, 7, 0x21380977000000),
+            (9.99e99, false, 99, 0x99900000000000),
+            (1e-99, false, -99, 0x10000000000000),
+        ];
+
+        for (value, negative, exponent, mantissa) in cases {
+            assert_eq!(
+                float_to_tifloat(value),
+                Float::new(negative, exponent, mantissa).unwrap(),
+                "converting {}",
+                value
+            );
+        }
+    }
+
+    /// Text keeps digits an `f64` can't hold, and the rounded result survives being stored in
+    /// one.
+    #[test]
+    fn text_to_tifloat_edge_cases() {
+        let cases = [
+            ("0", false, 0, 0x00000000000000),
+            ("-0.0", false, 0, 0x00000000000000),
+            ("1", false, 0, 0x10000000000000),
+            ("-2.5", true, 0, 0x25000000000000),
+            ("0.1", false, -1, 0x10000000000000),
+            ("0.3", false, -1, 0x30000000000000),
+            (".001", false, -3, 0x10000000000000),
+            ("1e-5", false, -5, 0x10000000000000),
+            ("1.5E3", false, 3, 0x15000000000000),
+            ("999.9999999", false, 2, 0x99999999990000),
+            ("0.1234567890123449999", false, -1, 0x12345678901234),
+            ("2.00000000000004999999", false, 0, 0x20000000000000),
+            ("99999999999999.5", false, 14, 0x10000000000000),
+            ("9.99e99", false, 99, 0x99900000000000),
+            ("1e-99", false, -99, 0x10000000000000),
+        ];
+
+        for (text, negative, exponent, mantissa) in cases {
+            let float = text_to_tifloat(text).unwrap();
+            assert_eq!(
+                float,
+                Float::new(negative, exponent, mantissa).unwrap(),
+                "converting {}",
+                text
+            );
+            assert_eq!(
+                float_to_tifloat(tifloat_to_float(&float)),
+                float,
+                "round-tripping {}",
+                text
+            );
+        }
+
+        for text in [
+            "",
+            "-",
+            ".",
+            "1.2.3",
+            "1e",
+            "0x10",
+            "one",
+            "1e100",
+            "9.999999999999999e99",
+        ] {
+            assert!(text_to_tifloat(text).is_err(), "converting {}", text);
+        }
+    }
+
+    #[test]
+    fn checked_float_to_tifloat_range() {
+        for value in [1e100, -1e100, 9.999999999999999e99, 1e-100] {
+            assert!(
+                checked_float_to_tifloat(value).is_err(),
+                "converting {}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn displayed_digits_round_guard_digits() {
+        let cases = [
+            (0.0, (false, 0, vec![0])),
+            (1.0000000000003, (false, 0, vec![1])),
+            (0.99999999999999, (false, 0, vec![1])),
+            (
+                0.9999999999,
+                (false, -1, vec![9, 9, 9, 9, 9, 9, 9, 9, 9, 9]),
+            ),
+            (
+                -12.3456789012345,
+                (true, 1, vec![1, 2, 3, 4, 5, 6, 7, 8, 9]),
+            ),
+        ];
+
+        for (value, expected) in cases {
+            assert_eq!(
+                displayed_digits(&float_to_tifloat(value)),
+                expected,
+                "displaying {}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn displayed_number_normal_mode() {
+        let cases = [
+            (0.0, "0"),
+            (120.0, "120"),
+            (-2.5, "~2.5"),
+            (1.0 / 3.0, ".3333333333"),
+            (0.01 / 3.0, ".0033333333"),
+            (0.001, ".001"),
+            (0.0001, "1|E~4"),
+            (9999999999.0, "9999999999"),
+            (-15e11, "~1.5|E12"),
+        ];
+
+        for (value, expected) in cases {
+            assert_eq!(displayed_number(value), expected, "displaying {}", value);
+        }
+    }
+
+    #[test]
+    fn tifloat_round_trip() {
+        for value in [0.0, 0.3, -2.5, 1e-5, 999.9999999, 21380977.0] {
+            assert_eq!(tifloat_to_float(&float_to_tifloat(value)), value);
+        }
+    }
+    #[test]
+    fn untokenizable_submission_names_its_line() {
+        let submission = "// a snowman\nDisp 1\n\nDisp \u{2603}\nDisp 2".to_owned();
+        assert_eq!(
+            process_submission(submission).err().unwrap(),
+            "Disp \u{2603}"
+        );
+    }
 }